@@ -14,14 +14,21 @@ fi
 echo "Building with profile: ${target_profile}"
 
 wasi_target="wasm32-wasip1"
+wasi_component_target="wasm32-wasip2"
 rustup target add ${wasi_target}
+rustup target add ${wasi_component_target}
 
 rm -f onnx-native-test
 rm -f wasmtime-test
 rm -f wasi-nn-onnx-test.wasm
-rm -f wasi-nn-onnx-test.wasm.SERIALIZED
+rm -f wasi-nn-onnx-test.component.wasm
 rm -f simple-onnx.wasm
-rm -f simple-onnx.wasm.SERIALIZED
+rm -f models/test-*
+
+echo "Generating the synthetic test models"
+cd ../test-models
+cargo run --profile ${target_profile} -- ../bin/models ../bin/images
+cd ../bin
 
 echo "Compiling the native ONNX runtime test program"
 cd ../native
@@ -36,6 +43,10 @@ cd ../wasm/wasi-nn
 cargo build --profile ${target_profile} --target=${wasi_target}
 cp target/${wasi_target}/${build_folder}/wasi-nn-onnx-test.wasm ../../bin
 
+echo "Compiling the wasi-nn Wasm component"
+cargo build --profile ${target_profile} --target=${wasi_component_target} --features component
+cp target/${wasi_component_target}/${build_folder}/wasi-nn-onnx-test.wasm ../../bin/wasi-nn-onnx-test.component.wasm
+
 echo "Compiling the simple, non wasi-nn, Wasm module"
 cd ../simple-onnx
 cargo build --profile ${target_profile} --target=${wasi_target}
@@ -6,9 +6,11 @@
 set -e
 
 target=$1
+# optional comma-separated Cranelift CPU feature flags for precompiling the guests, for example has_lse
+cpu_features=$2
 if [ -z "$target" ]
 then
-    echo "Usage: $0 <target_architecture>"
+    echo "Usage: $0 <target_architecture> [<cpu features>]"
     exit 1
 elif [ "$target" == "aarch64-unknown-linux-gnu" ]
 then
@@ -30,6 +32,7 @@ mkdir -p ${target}
 
 rm -f ${target}/onnx-native-test
 rm -f ${target}/wasmtime-test
+rm -f ${target}/*.cwasm
 
 echo "Compiling the native ONNX runtime test program"
 cd ../native
@@ -42,3 +45,24 @@ cargo build --profile release --target=${target}
 cp target/${target}/release/wasmtime-test ../../bin/${target}
 
 cd ../../bin
+
+# the guests are precompiled with the wasmtime-test of the host (from build_all.sh), so that the target
+# device runs them with --precompiled without compiling them
+if [ -f wasmtime-test ]
+then
+    compile_options="--target ${target}"
+    if [ -n "$cpu_features" ]
+    then
+        compile_options="${compile_options} --cpu-features ${cpu_features}"
+    fi
+    for guest in simple-onnx wasi-nn-onnx-test wasi-nn-onnx-test.component
+    do
+        if [ -f ${guest}.wasm ]
+        then
+            echo "Precompiling ${guest}.wasm"
+            ./wasmtime-test compile ${compile_options} --output ${target}/${guest}.cwasm ${guest}.wasm
+        fi
+    done
+else
+    echo "Skipping the precompilation of the guests: wasmtime-test has not been built for the host with build_all.sh"
+fi
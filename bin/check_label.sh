@@ -2,18 +2,24 @@
 
 index=$1;
 
+label_file=${2:-models/labels.txt}
+
 function echo_instructions {
-   echo "Usage: ./check_label.sh <index>";
-   echo "- <index> must be an integer between 1 and 1000 with no leading zeros";
+   echo "Usage: ./check_label.sh <index> [<label file>]";
+   echo "- <index> must be a non-negative integer with no leading zeros";
+   echo "- the label file has lines in the format <index>;<label> (default: models/labels.txt)";
 }
 
-if [[ ! "$index" =~ ^[1-9][0-9]*$ ]]
-then
-    echo_instructions;
-elif (( "$index" < 1 || "$index" > 1000 ))
+if [[ ! "$index" =~ ^(0|[1-9][0-9]*)$ ]]
 then
     echo_instructions;
 else
-    label=$(cat models/labels.txt | grep --max-count=1 "$index;" | cut -d';' -f2);
-    echo "Index ${index} corresponds to label: ${label}";
+    # the indexes are matched exactly, so the script works with both 0-based and 1-based label files
+    label=$(grep --max-count=1 "^${index};" "${label_file}" | cut -d';' -f2);
+    if [ -z "$label" ]
+    then
+        echo "Index ${index} is not in ${label_file}";
+    else
+        echo "Index ${index} corresponds to label: ${label}";
+    fi
 fi
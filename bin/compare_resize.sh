@@ -0,0 +1,34 @@
+#!/bin/bash
+
+# Compares the accuracy of the resize modes (squash, center crop and letterbox) with the evaluation mode
+# of the runners over a labeled image folder.
+# Usage: ./compare_resize.sh [<model> [<image folder> [<ground truth file>]]]
+
+model=${1:-models/mobilenetv2-10.onnx}
+image_folder=${2:-images}
+ground_truth=${3:-${image_folder}/ground-truth.txt}
+resize_modes="squash center_crop letterbox"
+
+run_evaluation() {
+    local runner=$1
+    shift
+    for resize_mode in ${resize_modes}
+    do
+        echo "${runner} with ${resize_mode}:"
+        "$@" --evaluate=${ground_truth} --resize=${resize_mode} | grep "accuracy"
+    done
+    echo
+}
+
+if [ -f onnx-native-test ]
+then
+    run_evaluation "native" ./onnx-native-test ${model} ${image_folder} 1
+fi
+if [ -f wasmtime-test ] && [ -f wasi-nn-onnx-test.wasm ]
+then
+    run_evaluation "wasi-nn" ./wasmtime-test --command wasi-nn-onnx-test.wasm ${model} ${image_folder} 1 --
+fi
+if [ -f wasmtime-test ] && [ -f simple-onnx.wasm ]
+then
+    run_evaluation "simple-onnx" ./wasmtime-test --command simple-onnx.wasm ${model} ${image_folder} 1 --
+fi
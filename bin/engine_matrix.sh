@@ -0,0 +1,47 @@
+#!/bin/bash
+
+# Runs the Wasm guests with wasmtime-test using different engine configurations and prints the engine
+# configuration with the timings of each run, for comparing how the engine settings affect the tract guest
+# (simple-onnx) and the wasi-nn guest.
+# Usage: ./engine_matrix.sh [<model> [<image> [<number of repeats>]]]
+
+model=${1:-models/mobilenetv2-10.onnx}
+image=${2:-images/husky.jpg}
+repeats=${3:-10}
+guests="simple-onnx.wasm wasi-nn-onnx-test.wasm"
+
+# one engine configuration on each line, the first one is the default configuration
+engine_configurations=(
+    ""
+    "--opt-level none"
+    "--opt-level speed_and_size"
+    "--no-relaxed-simd"
+    "--no-simd"
+    "--nan-canonicalization"
+    "--memory-reservation 0"
+    "--memory-guard-size 64K"
+    "--no-parallel-compilation"
+    "--compiler winch --no-simd"
+)
+
+if [ ! -f wasmtime-test ]
+then
+    echo "wasmtime-test has not been built to the bin folder"
+    exit 1
+fi
+
+for guest in ${guests}
+do
+    if [ ! -f ${guest} ]
+    then
+        echo "Skipping ${guest}: it has not been built to the bin folder"
+        continue
+    fi
+    for engine_options in "${engine_configurations[@]}"
+    do
+        echo "${guest}:"
+        ./wasmtime-test ${engine_options} --command ${guest} ${model} ${image} ${repeats} \
+            | grep -E "Engine configuration|took|${image}|Error"
+        echo
+    done
+done
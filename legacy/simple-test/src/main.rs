@@ -1,51 +1,64 @@
 extern crate wasmtime;
 extern crate wasmtime_wasi;
-extern crate wasi_common;
 extern crate anyhow;
-extern crate cap_std;
 extern crate local_names;
 
-use anyhow::{Ok, Result};
-use local_names::{get_image_index, get_model_index};
+use anyhow::{anyhow, Result};
+use local_names::{get_image_index, get_listing_hash, get_model_index, LISTING_HASH_ENV, MANIFEST_FILE};
 use std::{env, path::Path, time::Instant};
 use wasmtime::{Config, Engine, Module, Store};
-use wasi_common::{sync::Dir, sync::WasiCtxBuilder, WasiCtx};
-use wasmtime::component::__internal::wasmtime_environ::__core::result::Result::Ok as WasmtimeResultOk;
+use wasmtime_wasi::{preview1::WasiP1Ctx, DirPerms, FilePerms, WasiCtxBuilder};
 
 
 /// The host state for running wasi-nn tests.
 struct Ctx {
-    wasi: WasiCtx,
+    wasi: WasiP1Ctx,
 }
 impl Ctx {
-    fn new(directories: &Vec<&str>) -> Result<Self> {
-        let preopen_dirs = directories
-            .iter()
-            .map(|dir| {
-                Dir::open_ambient_dir(Path::new(dir), cap_std::ambient_authority())
-            }.unwrap());
-
-        let mut binding = WasiCtxBuilder::new();
-        let builder = binding.inherit_stdio();
-        for (preopen_dir, path) in preopen_dirs.zip(directories) {
-            builder.preopened_dir(preopen_dir, path)?;
+    /// Creates the host state with the given directories shared with the guest as read-only.
+    fn new(directories: &[&str], args: &[String], envs: &[(String, String)]) -> Result<Self> {
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .inherit_stdio()
+            .inherit_env()
+            .envs(envs)
+            .args(args);
+
+        for dir in directories {
+            if !Path::new(dir).is_dir() {
+                return Err(anyhow!("Directory to share with the guest does not exist: {}", dir));
+            }
+            builder
+                .preopened_dir(dir, dir, DirPerms::READ, FilePerms::READ)
+                .map_err(|error| anyhow!("Could not share the directory {} with the guest: {}", dir, error))?;
         }
 
-        let wasi = builder.build();
+        let wasi = builder.build_p1();
 
         Ok(Self { wasi })
     }
 }
 
 
-fn main() -> wasmtime::Result<()> {
+fn main() -> Result<()> {
     const WASM_MODULE_FILENAME: &str = "simple-onnx.wasm";
     const WASM_MODULE_SERIALIZED_FILENAME: &str = "simple-onnx.wasm.SERIALIZED";
     const MODEL_DIR: &str = "models";
     const IMAGE_DIR: &str = "images";
-    let shared_dirs: Vec<&str> = vec![MODEL_DIR, IMAGE_DIR];
+    let mut shared_dirs: Vec<&str> = vec![MODEL_DIR, IMAGE_DIR];
+    if Path::new(MANIFEST_FILE).is_file() {
+        // the guest reads the same manifest for the model and image indexes
+        shared_dirs.push(".");
+    }
 
     let args: Vec<String> = env::args().collect();
+    let listing_hash = match get_listing_hash() {
+        Ok(hash) => hash,
+        Err(error) => {
+            println!("{}", error);
+            return Ok(());
+        }
+    };
     let model_filename: &str = &args[1];
     let image_name: &str = &args[2];
     let model_index = match get_model_index(model_filename) {
@@ -64,27 +77,31 @@ fn main() -> wasmtime::Result<()> {
     };
     let repeats: u32 = args[3].parse().unwrap();
 
+    // the guest gets the same arguments as this program, with the module as the program name
+    let mut guest_args: Vec<String> = vec![WASM_MODULE_FILENAME.to_string()];
+    guest_args.extend_from_slice(&args[1..]);
+
     let start: Instant = Instant::now();
 
     let config = Config::default();
     let engine = Engine::new(&config)?;
     let mut linker = wasmtime::Linker::new(&engine);
 
-    wasi_common::sync::add_to_linker(&mut linker, |host: &mut Ctx| &mut host.wasi)?;
+    wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |host: &mut Ctx| &mut host.wasi)?;
 
     let mut store = Store::new(
         &engine,
-        Ctx::new(&shared_dirs)?
+        Ctx::new(&shared_dirs, &guest_args, &[(LISTING_HASH_ENV.to_string(), listing_hash)])?
     );
     let environment_set_time = start.elapsed();
 
     let wasm_module =
         match unsafe { Module::deserialize_file(&engine, WASM_MODULE_SERIALIZED_FILENAME) } {
-            WasmtimeResultOk(serialized_module) => serialized_module,
+            Ok(serialized_module) => serialized_module,
             Err(_) => {
                 let loaded_module = Module::from_file(&engine, WASM_MODULE_FILENAME)?;
                 let byte_module = loaded_module.serialize()?;
-                std::fs::write(WASM_MODULE_SERIALIZED_FILENAME, byte_module).unwrap();
+                std::fs::write(WASM_MODULE_SERIALIZED_FILENAME, byte_module)?;
 
                 loaded_module
             }
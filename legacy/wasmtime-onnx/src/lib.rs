@@ -20,4 +20,7 @@ pub enum WasiNnError {
 
     #[error("Invalid encoding")]
     InvalidEncodingError,
+
+    #[error("model load error")]
+    ModelLoadError,
 }
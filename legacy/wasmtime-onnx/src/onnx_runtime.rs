@@ -9,8 +9,9 @@ use crate::{
     },
     WasiNnError, WasiNnResult as Result,
 };
+use local_names::{sha256_bytes, ModelZoo};
 use ndarray::{Array, Dim, IxDynImpl};
-use ort::{CPUExecutionProvider, GraphOptimizationLevel, Session, Tensor as OrtTensor, TensorElementType, Value};
+use ort::{CPUExecutionProvider, GraphOptimizationLevel, Session, Tensor as OrtTensor, TensorElementType};
 use std::{
     borrow::BorrowMut,
     collections::{btree_map::Keys, BTreeMap},
@@ -22,6 +23,34 @@ use std::{
 #[derive(Default)]
 pub struct WasiNnOnnxCtx {
     pub state: Arc<RwLock<State>>,
+    /// The SHA-256 checksums (lowercase hexadecimal) of the models that are allowed to be loaded.
+    /// When set, loading any other model fails. When not set, all models are accepted.
+    pub model_checksums: Option<Vec<String>>,
+}
+
+impl WasiNnOnnxCtx {
+    /// Creates the context that only loads the models with the `sha256` checksums in the sidecar metadata
+    /// of the models in the model zoo. All models are accepted if none of the sidecars has a checksum.
+    pub fn with_model_zoo(model_zoo: &ModelZoo) -> Self {
+        let checksums = model_zoo.checksums();
+        Self {
+            state: Arc::default(),
+            model_checksums: if checksums.is_empty() { None } else { Some(checksums) },
+        }
+    }
+
+    /// Checks the SHA-256 checksum of the model bytes against the allowed checksums, if they are set.
+    pub fn verify_checksum(&self, model_bytes: &[u8]) -> Result<()> {
+        let checksum = sha256_bytes(model_bytes);
+        log::info!("load: model SHA-256: {}", checksum);
+        if let Some(model_checksums) = &self.model_checksums {
+            if !model_checksums.iter().any(|expected| expected.eq_ignore_ascii_case(&checksum)) {
+                log::error!("load: the model SHA-256 {} does not match any of the expected checksums", checksum);
+                return Err(WasiNnError::ModelLoadError);
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Struct to hold the non-instantiated models and execution sessions.
@@ -89,6 +118,30 @@ impl State {
     }
 }
 
+/// Creates an ONNX Runtime session with the CPU execution provider for the model bytes.
+pub fn create_session(model_bytes: &[u8]) -> Result<Session> {
+    ort::init()
+        .with_execution_providers([CPUExecutionProvider::default().build()])
+        .commit()?;
+
+    let session = Session::builder()?
+        .with_optimization_level(GraphOptimizationLevel::Level3)?
+        .commit_from_memory(model_bytes)?;
+    Ok(session)
+}
+
+/// Runs the session with the F32 input tensor and returns the output tensors.
+pub fn run_session(
+    session: &Session,
+    input: Array<f32, Dim<IxDynImpl>>,
+) -> Result<Vec<Array<f32, Dim<IxDynImpl>>>> {
+    let outputs = session.run([OrtTensor::from_array(input)?.into()])?;
+    outputs
+        .values()
+        .map(|output| Ok(output.try_extract_tensor::<f32>()?.to_owned()))
+        .collect()
+}
+
 impl WasiEphemeralNn for WasiNnOnnxCtx {
     fn load(
         &mut self,
@@ -104,6 +157,8 @@ impl WasiEphemeralNn for WasiNnOnnxCtx {
         }
 
         let model_bytes = builder.as_ptr().read()?.as_slice()?.unwrap().to_vec();
+        self.verify_checksum(&model_bytes)?;
+
         let mut state = self.state.write()?;
         let graph = state.key(state.models.keys());
         log::info!(
@@ -133,14 +188,7 @@ impl WasiEphemeralNn for WasiNnOnnxCtx {
             }
         };
 
-        ort::init()
-            .with_execution_providers([CPUExecutionProvider::default().build()])
-            .commit()?;
-
-        let session = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_model_from_memory(model_bytes)?;
-        let session = OnnxSession::with_session(session)?;
+        let session = OnnxSession::with_session(create_session(model_bytes)?)?;
         let gec = state.key(state.executions.keys());
         log::info!(
             "init_execution_context: inserting graph execution context: {:#?}",
@@ -277,7 +325,7 @@ impl WasiEphemeralNn for WasiNnOnnxCtx {
         let bytes = f32_vec_to_bytes(tensor.as_slice().unwrap().to_vec());
         let size = bytes.len();
         let out_slice = out_buffer.as_array(out_buffer_max_size).as_slice_mut()?;
-        (&mut out_slice.unwrap()[..size]).copy_from_slice(&bytes);
+        out_slice.unwrap()[..size].copy_from_slice(&bytes);
 
         Ok(size as BufferSize)
     }
@@ -307,13 +355,14 @@ impl WasiEphemeralNn for WasiNnOnnxCtx {
             input_tensors.len()
         );
 
-        let session_input: Value = Value::from_array(input_tensors.first().unwrap().clone())?;
-        let binding = execution.session.run([session_input])?;
-        let output_tensors: Vec<OrtTensor<'_, f32>> =
-            binding
-                .iter()
-                .map(|(_, output_value)| output_value.extract_tensor::<f32>().unwrap())
-                .collect::<Vec<OrtTensor<f32>>>();
+        let input = match input_tensors.first() {
+            Some(input) => input.clone(),
+            None => {
+                log::error!("compute: no input tensor. Perhaps you haven't called set_input yet?");
+                return Err(WasiNnError::RuntimeError);
+            }
+        };
+        let output_tensors = run_session(&execution.session, input)?;
 
         log::info!(
             "compute: output tensors contains {} elements",
@@ -322,7 +371,7 @@ impl WasiEphemeralNn for WasiNnOnnxCtx {
 
         log::info!(
             "compute: dimensions of first output tensor: {:#?}",
-            output_tensors.get(0).unwrap().view().dim()
+            output_tensors.first().map(|tensor| tensor.shape())
         );
 
         match execution.output_tensors {
@@ -330,10 +379,10 @@ impl WasiEphemeralNn for WasiNnOnnxCtx {
                 log::error!("compute: existing data in output_tensors, aborting");
                 // return Err(WasiNnError::RuntimeError);
                 // TODO: ignore the error and just overwrite the existing output tensors
-                execution.output_tensors = Some(output_vec_from_tensors(output_tensors)?);
+                execution.output_tensors = Some(output_tensors);
             }
             None => {
-                execution.output_tensors = Some(output_vec_from_tensors(output_tensors)?);
+                execution.output_tensors = Some(output_tensors);
             }
         };
         Ok(())
@@ -346,19 +395,19 @@ impl From<ort::Error> for WasiNnError {
     }
 }
 
-impl<'a> From<PoisonError<std::sync::RwLockReadGuard<'_, State>>> for WasiNnError {
+impl From<PoisonError<std::sync::RwLockReadGuard<'_, State>>> for WasiNnError {
     fn from(_: PoisonError<RwLockReadGuard<'_, State>>) -> Self {
         WasiNnError::RuntimeError
     }
 }
 
-impl<'a> From<PoisonError<RwLockWriteGuard<'_, State>>> for WasiNnError {
+impl From<PoisonError<RwLockWriteGuard<'_, State>>> for WasiNnError {
     fn from(_: PoisonError<RwLockWriteGuard<'_, State>>) -> Self {
         WasiNnError::RuntimeError
     }
 }
 
-impl<'a> From<PoisonError<&mut State>> for WasiNnError {
+impl From<PoisonError<&mut State>> for WasiNnError {
     fn from(_: PoisonError<&mut State>) -> Self {
         WasiNnError::RuntimeError
     }
@@ -375,7 +424,7 @@ impl From<TensorType> for TensorElementType {
     }
 }
 
-impl<'a> UserErrorConversion for WasiNnOnnxCtx {
+impl UserErrorConversion for WasiNnOnnxCtx {
     fn nn_errno_from_wasi_nn_error(
         &mut self,
         e: WasiNnError,
@@ -386,20 +435,27 @@ impl<'a> UserErrorConversion for WasiNnOnnxCtx {
             WasiNnError::RuntimeError => unimplemented!(),
             WasiNnError::OnnxError => unimplemented!(),
             WasiNnError::InvalidEncodingError => unimplemented!(),
+            WasiNnError::ModelLoadError => Ok(NnErrno::InvalidArgument),
         }
     }
 }
 
-fn output_vec_from_tensors(
-    tensors: Vec<OrtTensor<'_, f32>>,
-) -> Result<Vec<Array<f32, Dim<IxDynImpl>>>> {
-    let mut res = Vec::new();
-    for (_, tensor) in tensors.into_iter().enumerate() {
-        res.push(Array::from_shape_vec(
-            tensor.view().shape(),
-            tensor.view().as_slice().unwrap().to_vec(),
-        )?);
-    }
 
-    Ok(res)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_checksum() {
+        let model = b"model bytes";
+        assert!(WasiNnOnnxCtx::default().verify_checksum(model).is_ok());
+
+        // the checksums in the sidecars may be written in uppercase
+        let context = WasiNnOnnxCtx {
+            model_checksums: Some(vec![sha256_bytes(model).to_uppercase()]),
+            ..WasiNnOnnxCtx::default()
+        };
+        assert!(context.verify_checksum(model).is_ok());
+        assert!(matches!(context.verify_checksum(b"other model"), Err(WasiNnError::ModelLoadError)));
+    }
 }
@@ -0,0 +1,26 @@
+// Runs the synthetic test models from the test-models crate through the ONNX Runtime session of the legacy backend,
+// so no downloaded models are needed.
+
+extern crate ndarray;
+extern crate test_models;
+extern crate wasmtime_onnx;
+
+use ndarray::Array;
+use test_models::{check_models, to_bytes, ModelCase};
+use wasmtime_onnx::{
+    onnx_runtime::{create_session, run_session},
+    WasiNnError,
+};
+
+
+fn run_model(case: &ModelCase) -> Result<Vec<f32>, WasiNnError> {
+    let session = create_session(&to_bytes(&case.model))?;
+    let input = Array::from_shape_vec(case.input_shape.clone(), case.input.clone())?;
+    let outputs = run_session(&session, input)?;
+    Ok(outputs.first().map(|output| output.iter().cloned().collect()).unwrap_or_default())
+}
+
+#[test]
+fn test_models() {
+    check_models(run_model);
+}
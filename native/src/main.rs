@@ -1,133 +1,104 @@
 extern crate image;
 extern crate ort;
 extern crate ndarray;
+extern crate local_names;
+#[cfg(test)]
+extern crate test_models;
 
-use std::{env, cmp::Ordering, convert::TryInto, ops::RangeFrom, time::{Duration, Instant}};
-use image::{imageops::FilterType, ImageBuffer, ImageError, Rgb};
+use std::{env, convert::TryInto, process, time::{Duration, Instant}};
+use image::{imageops::FilterType, DynamicImage, ImageBuffer, Rgb};
 use ndarray::{Array4, OwnedRepr, prelude::{ArrayBase, Dim}};
 use ort::{GraphOptimizationLevel, Session, Value};
+use local_names::{validate_output, Arguments, ErrorContext, ErrorType, ModelMetadata, RunnerError, EVALUATE_OPTION, RESIZE_OPTION, TOP_COUNT};
+use local_names::evaluation::{self, ground_truth_file, resize_override};
+
+/// The option for printing the SHA-256 checksum of the model file.
+const PRINT_HASH_OPTION: &str = "print-hash";
 
 type RawImage = ImageBuffer<Rgb<u8>, Vec<u8>>;
 type NormalizedImage = ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>>;
 
-#[derive(Debug)]
-pub enum ErrorType {
-    SessionCreation,
-    Optimization,
-    Threads,
-    ModelLoad,
-    ImageLoad,
-    ImageConversion,
-    ModelRun,
-    TensorExtract,
-    NoResult,
-    MissingImageName,
-}
-
-
-fn load_model(filename: &str) -> Result<Session, ErrorType> {
+fn load_model(filename: &str) -> Result<Session, RunnerError> {
     Session::builder()
-        .map_err(|error| {
-            eprintln!("Error creating session: {:?}", error);
-            ErrorType::SessionCreation
-        })?
+        .error_context(ErrorType::SessionCreation, || "Could not create the session builder")?
         .with_optimization_level(GraphOptimizationLevel::Level3)
-        .map_err(|error| {
-            eprintln!("Error setting optimization level: {:?}", error);
-            ErrorType::Optimization
-        })?
+        .error_context(ErrorType::Optimization, || "Could not set the optimization level")?
         // .with_inter_threads(12)
-        // .map_err(|error| {
-        //     eprintln!("Error setting inter threads: {:?}", error);
-        //     ErrorType::Threads
-        // })?
+        // .error_context(ErrorType::Threads, || "Could not set the inter threads")?
         // .with_intra_threads(12)
-        // .map_err(|error| {
-        //     eprintln!("Error setting intra threads: {:?}", error);
-        //     ErrorType::Threads
-        // })?
+        // .error_context(ErrorType::Threads, || "Could not set the intra threads")?
         .commit_from_file(filename)
-        .map_err(|error| {
-            eprintln!("Error loading model: {:?}", error);
-            ErrorType::ModelLoad
-        })
+        .error_context(ErrorType::ModelLoad, || format!("Could not load the model {}", filename))
 }
 
-fn load_image(path: &str, nwidth: u32, nheight: u32, filter: FilterType) -> Result<Value, ErrorType> {
-    let image: Result<RawImage, ImageError> = image::open(path)
-        .map(|image| image.resize_exact(nwidth, nheight, filter).to_rgb8());
-
-    match image {
-        Ok(image) => {
-            let normalized_image: NormalizedImage = Array4::from_shape_fn(
-                (1, 3, nwidth as usize, nheight as usize),
-                |(_, color, y, x)| {
-                    // color normalization magic from
-                    // https://github.com/sonos/tract/tree/fd690600d1993ca4b90e2e73db4c5dccbecf5ded/examples/onnx-mobilenet-v2
-                    let mean = [0.485, 0.456, 0.406][color];
-                    let std = [0.229, 0.224, 0.225][color];
-                    (image[(x as u32, y as u32)][color] as f32 / 255.0 - mean) / std
-                }
-            );
-
-            TryInto::<Value>::try_into(normalized_image)
-                .map_err(|error| {
-                    eprintln!("Error converting image to input value: {:?}", error);
-                    ErrorType::ImageConversion
-                })
-        },
-        Err(error) => {
-            eprintln!("Error loading image: {:?}", error);
-            Err(ErrorType::ImageLoad)
-        }
-    }
+/// Resizes the image to the input size of the model according to its resize mode and creates the normalized
+/// input tensor data from it.
+fn preprocess_image(image: &DynamicImage, metadata: &ModelMetadata, filter: FilterType) -> Vec<f32> {
+    let geometry = metadata.resize_geometry(image.width(), image.height());
+    let image: RawImage = image.resize_exact(geometry.width, geometry.height, filter).to_rgb8();
+    // color normalization magic from
+    // https://github.com/sonos/tract/tree/fd690600d1993ca4b90e2e73db4c5dccbecf5ded/examples/onnx-mobilenet-v2
+    metadata.normalize_resized_pixels(&geometry, |x, y, color| image[(x, y)][color])
 }
 
-fn run_model<'model>(model: &'model Session, image: Value) -> Result<ort::SessionOutputs<'model>, ErrorType> {
-    // let cloned_image = image.clone();
-    model.run([image.into()])
-        .map_err(|error| {
-            eprintln!("Error running model: {:?}", error);
-            ErrorType::ModelRun
-        })
+fn load_image(path: &str, metadata: &ModelMetadata, filter: FilterType) -> Result<Value, RunnerError> {
+    let image: DynamicImage = image::open(path)
+        .error_context(ErrorType::ImageLoad, || format!("Could not load the image {}", path))?;
+
+    let data = preprocess_image(&image, metadata, filter);
+    let normalized_image: NormalizedImage = Array4::from_shape_vec(metadata.input_dimensions(), data)
+        .error_context(ErrorType::ImageConversion, || "Could not create the input array")?;
+
+    TryInto::<Value>::try_into(normalized_image)
+        .error_context(ErrorType::ImageConversion, || "Could not convert the image to an input value")
 }
 
-fn get_result(model: &Session, image_name: &str, verbose: bool) -> Result<(f32, i32), ErrorType> {
-    const MODEL_IMAGE_WIDTH: u32 = 224;
-    const MODEL_IMAGE_HEIGHT: u32 = 224;
+fn run_model<'model>(model: &'model Session, image: &Value) -> Result<ort::SessionOutputs<'model>, RunnerError> {
+    // the input is given as a view so that it can still be read if the output is invalid
+    model.run([image.view().into()])
+        .error_context(ErrorType::ModelRun, || "Could not run the model")
+}
+
+fn get_result(model: &Session, metadata: &ModelMetadata, image_name: &str, verbose: bool) -> Result<(f32, i32), RunnerError> {
+    get_top_results(model, metadata, image_name, 1, verbose)
+        .and_then(|results| results.first().cloned().ok_or_else(|| ErrorType::NoResult.into()))
+}
+
+/// Runs the inference with the image and returns the `count` best scores with their class indexes.
+fn get_top_results(
+    model: &Session,
+    metadata: &ModelMetadata,
+    image_name: &str,
+    count: usize,
+    verbose: bool
+) -> Result<Vec<(f32, i32)>, RunnerError> {
     const MODEL_IMAGE_FILTER_TYPE: FilterType = FilterType::Triangle;
 
     // load the image and run the model
     let result_start: Instant = Instant::now();
-    let image_result = load_image(image_name, MODEL_IMAGE_WIDTH, MODEL_IMAGE_HEIGHT, MODEL_IMAGE_FILTER_TYPE);
+    let image = load_image(image_name, metadata, MODEL_IMAGE_FILTER_TYPE)?;
     let image_load_duration: Duration = result_start.elapsed();
-    let mut model_output = image_result.and_then(|image| run_model(&model, image))?;
+    let mut model_output = run_model(model, &image)?;
     let model_run_duration: Duration = result_start.elapsed() - image_load_duration;
 
     // extract the results
     let model_results = match model_output.pop_first() {
         Some((_, value)) => value,
-        None => {
-            eprintln!("No result found");
-            return Err(ErrorType::NoResult);
-        },
+        None => return Err(RunnerError::new(ErrorType::NoResult, "The model has no outputs")),
     };
-    let output_tensor = match model_results.try_extract_tensor::<f32>() {
-        Ok(value) => value,
-        Err(error) => {
-            eprintln!("Error extracting tensor: {:?}", error);
-            return Err(ErrorType::TensorExtract)
-        },
+    let output_tensor = model_results
+        .try_extract_tensor::<f32>()
+        .error_context(ErrorType::TensorExtract, || "Could not extract the output tensor")?;
+
+    // find the highest scores and the corresponding labels
+    let scores: Vec<f32> = output_tensor.view().iter().cloned().collect();
+    validate_output(&scores, &metadata.input_dimensions(), || {
+        image.try_extract_raw_tensor::<f32>().map(|(_, data)| data.to_vec()).unwrap_or_default()
+    })?;
+    let final_result = match metadata.top_results(&scores, count, verbose) {
+        results if results.is_empty() => Err(ErrorType::NoResult.into()),
+        results => Ok(results),
     };
-
-    // find the highest score and the corresponding label
-    let final_result = output_tensor
-        .view()
-        .iter()
-        .cloned()
-        .zip(RangeFrom::<i32>{start: 1})  // add the indexes for the labels
-        .max_by(|(score1, _), (score2, _)| score1.partial_cmp(score2).unwrap_or(Ordering::Equal))
-        .map_or_else(|| Err(ErrorType::NoResult), Ok);
     let final_duration: Duration = result_start.elapsed() - image_load_duration - model_run_duration;
 
     if verbose {
@@ -139,34 +110,47 @@ fn get_result(model: &Session, image_name: &str, verbose: bool) -> Result<(f32,
     final_result
 }
 
-fn get_error_code(error: ErrorType) -> i32 {
-    match error {
-        ErrorType::SessionCreation => -1,
-        ErrorType::Optimization => -2,
-        ErrorType::Threads => -3,
-        ErrorType::ModelLoad => -4,
-        ErrorType::ImageLoad => -5,
-        ErrorType::ImageConversion => -6,
-        ErrorType::ModelRun => -7,
-        ErrorType::TensorExtract => -8,
-        ErrorType::NoResult => -9,
-        ErrorType::MissingImageName => -10,
+/// Runs the inference or the evaluation with the command line arguments. On errors the process exits with
+/// the absolute value of the error code.
+pub fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {}", error.report());
+        process::exit(error.exit_code());
     }
 }
 
-pub fn main() -> Result<(), i32> {
+fn run() -> Result<(), RunnerError> {
     // tracing_subscriber::fmt::init();
 
     let args: Vec<String> = env::args().collect();
-    if args.len() != 4 {
-        println!("Usage: {} <model> <image> <number of repeats2>", args[0]);
-        return Err(-10);
+    let arguments = match Arguments::parse(&args, &[PRINT_HASH_OPTION, EVALUATE_OPTION, RESIZE_OPTION]) {
+        Ok(arguments) => arguments,
+        Err(error) => {
+            println!("{}\n", Arguments::usage(&args[0], &[PRINT_HASH_OPTION, EVALUATE_OPTION, RESIZE_OPTION]));
+            return Err(RunnerError::new(ErrorType::MissingImageName, error));
+        }
+    };
+    let resize_mode = resize_override(&arguments)?;
+    let ground_truth_file = ground_truth_file(&arguments)?;
+
+    let model_filename: &str = &arguments.model;
+    let image_name: &str = &arguments.image;
+    let repeats: u32 = arguments.repeats;
+
+    let metadata = ModelMetadata::for_model(model_filename)
+        .map_err(|error| RunnerError::new(ErrorType::ModelLoad, format!("Could not load the model metadata: {}", error)))?;
+
+    // verify the model file before loading it, the checksum is only computed when it is needed
+    let print_hash = arguments.option(PRINT_HASH_OPTION).is_some();
+    if metadata.sha256.is_some() || print_hash {
+        let checksum = metadata
+            .verify_checksum(model_filename)
+            .map_err(|error| RunnerError::new(ErrorType::ModelLoad, format!("Could not verify the model: {}", error)))?;
+        if print_hash {
+            println!("Model SHA-256: {}", checksum);
+        }
     }
 
-    let model_filename: &str = &args[1];
-    let image_name: &str = &args[2];
-    let repeats: u32 = args[3].parse().unwrap();
-
     // initialize the environment
     let start: Instant = Instant::now();
     let used_execution_providers = [
@@ -175,15 +159,10 @@ pub fn main() -> Result<(), i32> {
         // ort::OpenVINOExecutionProvider::default().build(),
         ort::CPUExecutionProvider::default().build()
     ];
-    let _ = match ort::init()
+    ort::init()
         .with_execution_providers(used_execution_providers)
-        .commit() {
-        Ok(_) => (),
-        Err(error) => {
-            eprintln!("Error initializing ONNXRuntime: {:?}", error);
-            return Err(get_error_code(ErrorType::SessionCreation));
-        }
-    };
+        .commit()
+        .error_context(ErrorType::SessionCreation, || "Could not initialize ONNX Runtime")?;
     let environment_duration: Duration = start.elapsed();
     println!("Initializing the environment took {:?}", environment_duration);
 
@@ -191,29 +170,41 @@ pub fn main() -> Result<(), i32> {
     let duration1 = start.elapsed() - environment_duration;
     println!("Loading the model took {:?}", duration1);
 
-    let model = match model_result {
-        Ok(session) => session,
-        Err(error) => return Err(get_error_code(error)),
-    };
+    let model = model_result?;
+
+    // with a ground truth file the image is a folder and the accuracy over its images is evaluated
+    if let Some(ground_truth_file) = ground_truth_file {
+        // the resize mode can be overridden for comparing the accuracy of the modes
+        let mut metadata = metadata.clone();
+        if let Some(resize_mode) = resize_mode {
+            metadata.resize = resize_mode;
+        }
+        return evaluation::print_evaluation(model_filename, image_name, ground_truth_file, |image| {
+            get_top_results(&model, &metadata, image, TOP_COUNT, false)
+                .map(|results| results.into_iter().map(|(_, class)| class).collect())
+        });
+    }
 
-    let result = get_result(&model, image_name, true);
+    let result = get_result(&model, &metadata, image_name, true);
     let duration2: Duration = start.elapsed() - environment_duration - duration1;
 
     for _ in 0..repeats {
-        let _ = get_result(&model, image_name, false);
+        let _ = get_result(&model, &metadata, image_name, false);
     }
     let duration3: Duration = start.elapsed() - environment_duration - duration1 - duration2;
 
     println!("\nRunning the model {} times took {:?}\n", repeats, duration3);
 
-    match result {
-        Ok((score, class)) => {
-            println!("{}: {} (score: {})", image_name, class, score);
-            Ok(())
-        },
-        Err(error) => {
-            println!("Error: {:?}", error);
-            Err(get_error_code(error))
-        }
-    }
+    let (score, class) = result?;
+    println!("{}: {} (score: {})", image_name, class, score);
+    metadata.check_result(image_name, class);
+    Ok(())
+}
+
+
+#[test]
+fn test_preprocess_image() {
+    test_models::preprocessing::check_preprocessing(|image, metadata| {
+        Ok::<Vec<f32>, RunnerError>(preprocess_image(image, metadata, FilterType::Triangle))
+    });
 }
@@ -0,0 +1,21 @@
+// Golden-output regression tests: runs the native program with the models in the bin folder and the bundled images
+// with the checks of `test_models::golden`.
+
+extern crate test_models;
+
+use std::process::Command;
+use test_models::golden::{check_golden_outputs, REPEATS};
+
+const BIN_FOLDER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../bin");
+
+
+#[test]
+fn golden_outputs() {
+    check_golden_outputs(BIN_FOLDER, "onnx-native-test", |model, image| {
+        Command::new(env!("CARGO_BIN_EXE_onnx-native-test"))
+            .args([model, image, REPEATS])
+            .current_dir(BIN_FOLDER)
+            .output()
+            .expect("could not run onnx-native-test")
+    });
+}
@@ -0,0 +1,85 @@
+//! The golden-output regression tests of the runners: runs a program with the models in the bin folder and the bundled
+//! images and checks the top-1 class and score against the expected values in the sidecar metadata of the model.
+//! Missing model files and models without the expected values are skipped.
+
+use local_names::ModelMetadata;
+use std::{fs, path::Path, process::Output};
+
+/// The folder of the models relative to the bin folder.
+pub const MODEL_FOLDER: &str = "models";
+/// The bundled images in the `images` folder of the bin folder.
+pub const GOLDEN_IMAGES: [&str; 4] = ["husky.jpg", "golden-retriever.jpg", "landrover.jpg", "bigmac.png"];
+/// The repeat count given to the programs, a single inference for each image.
+pub const REPEATS: &str = "0";
+
+
+/// The model files in the bin folder relative to it, sorted by name.
+pub fn model_files(bin_folder: &str) -> Vec<String> {
+    let mut models: Vec<String> = fs::read_dir(Path::new(bin_folder).join(MODEL_FOLDER))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.ends_with(".onnx"))
+                .map(|name| format!("{}/{}", MODEL_FOLDER, name))
+                .collect()
+        })
+        .unwrap_or_default();
+    models.sort();
+    models
+}
+
+/// Finds the result line `<image>: <class> (score: <score>)` from the output of the program.
+pub fn parse_result(output: &str, image: &str) -> Option<(i32, f32)> {
+    let prefix = format!("{}: ", image);
+    output.lines().find_map(|line| {
+        let (class, score) = line.strip_prefix(&prefix)?.split_once(" (score: ")?;
+        Some((class.trim().parse().ok()?, score.trim_end_matches(')').parse().ok()?))
+    })
+}
+
+/// Runs the program with every model in the bin folder and every golden image with an expected class and checks
+/// the results. The function `run` gets the model and the image relative to the bin folder and runs the program
+/// in the bin folder, `program` names the program in the messages.
+pub fn check_golden_outputs<F>(bin_folder: &str, program: &str, run: F)
+where F: Fn(&str, &str) -> Output {
+    let models = model_files(bin_folder);
+    if models.is_empty() {
+        eprintln!("Skipping the golden-output tests for {}: no models in {}/{}", program, bin_folder, MODEL_FOLDER);
+        return;
+    }
+
+    for model in models {
+        let metadata = ModelMetadata::for_model(&format!("{}/{}", bin_folder, model))
+            .unwrap_or_else(|error| panic!("{}", error));
+
+        for image_name in GOLDEN_IMAGES.iter() {
+            let expected_class = match metadata.expected_class(image_name) {
+                Some(class) => class,
+                None => {
+                    eprintln!("Skipping {} with {}: no expected class in the metadata", image_name, model);
+                    continue;
+                }
+            };
+
+            let image = format!("images/{}", image_name);
+            let output = run(&model, &image);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+
+            let (class, score) = parse_result(&stdout, &image).unwrap_or_else(|| {
+                panic!(
+                    "No result for {} with {} in {}:\n{}{}",
+                    image, model, program, stdout, String::from_utf8_lossy(&output.stderr)
+                )
+            });
+            assert_eq!(class, expected_class, "wrong class for {} with {} in {}", image, model, program);
+            if let Some(expected_score) = metadata.expected_score(image_name) {
+                assert!(
+                    metadata.is_score_within_tolerance(expected_score, score),
+                    "the score {} for {} with {} in {} differs from the expected score {}",
+                    score, image, model, program, expected_score
+                );
+            }
+        }
+    }
+}
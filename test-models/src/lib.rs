@@ -0,0 +1,372 @@
+//! Generates small deterministic ONNX models so that the runners and the backends can be tested offline
+//! without downloading MobileNet: an identity model, a single 1x1 convolution and a tiny color classifier.
+//!
+//! The classifier takes the average of each color channel of the input image (`GlobalAveragePool`),
+//! scales them with a diagonal `Gemm` and gives the probabilities of the classes red, green and blue with
+//! `Softmax`, so its output for any image can be computed by hand with `classifier_reference`.
+//!
+//! The `preprocessing` module has the reference tensors for testing the image preprocessing of the runners and
+//! the `golden` module has the golden-output regression tests of the runners.
+
+extern crate image;
+extern crate local_names;
+extern crate prost;
+extern crate tract_onnx;
+
+pub mod golden;
+pub mod preprocessing;
+
+use local_names::{sha256_bytes, top_results, ModelMetadata};
+use prost::Message;
+use std::{collections::BTreeMap, fs, io, path::Path};
+use tract_onnx::pb::{
+    tensor_proto::DataType,
+    tensor_shape_proto::{dimension, Dimension},
+    type_proto, AttributeProto, GraphProto, ModelProto, NodeProto, OperatorSetIdProto, TensorProto,
+    TensorShapeProto, TypeProto, ValueInfoProto,
+};
+use tract_onnx::prelude::{tvec, Framework, InferenceModelExt, Tensor};
+
+/// The file names of the generated models.
+pub const IDENTITY_MODEL: &str = "test-identity.onnx";
+pub const CONV_MODEL: &str = "test-conv.onnx";
+pub const CLASSIFIER_MODEL: &str = "test-classifier.onnx";
+/// The label file of the classifier.
+pub const CLASSIFIER_LABELS: &str = "test-classifier-labels.txt";
+/// The labels of the classifier classes, the class indexes are 1-based.
+pub const CLASSIFIER_CLASSES: [&str; 3] = ["red", "green", "blue"];
+/// The width and height of the input image of the classifier.
+pub const CLASSIFIER_INPUT_SIZE: u32 = 16;
+/// The scale of the channel averages in the classifier, makes the probabilities clearly different.
+pub const CLASSIFIER_SCALE: f32 = 10.0;
+/// The shape of the input and output of the identity model and the input of the convolution model.
+pub const IMAGE_SHAPE: [i64; 4] = [1, 3, 4, 4];
+
+/// The allowed absolute difference between the output of a model and the expected output.
+pub const OUTPUT_TOLERANCE: f32 = 1e-5;
+
+const IR_VERSION: i64 = 7;
+const OPSET_VERSION: i64 = 13;
+const PRODUCER_NAME: &str = "test-models";
+const INPUT_NAME: &str = "input";
+const OUTPUT_NAME: &str = "output";
+
+
+fn tensor_type(shape: &[i64]) -> Option<TypeProto> {
+    let dim = shape
+        .iter()
+        .map(|value| Dimension { value: Some(dimension::Value::DimValue(*value)), ..Default::default() })
+        .collect();
+    Some(TypeProto {
+        value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+            elem_type: DataType::Float as i32,
+            shape: Some(TensorShapeProto { dim }),
+        })),
+        ..Default::default()
+    })
+}
+
+fn value_info(name: &str, shape: &[i64]) -> ValueInfoProto {
+    ValueInfoProto { name: name.to_string(), r#type: tensor_type(shape), ..Default::default() }
+}
+
+fn float_tensor(name: &str, dims: &[i64], data: Vec<f32>) -> TensorProto {
+    TensorProto {
+        name: name.to_string(),
+        dims: dims.to_vec(),
+        data_type: DataType::Float as i32,
+        float_data: data,
+        ..Default::default()
+    }
+}
+
+fn int_attribute(name: &str, value: i64) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: tract_onnx::pb::attribute_proto::AttributeType::Int as i32,
+        i: value,
+        ..Default::default()
+    }
+}
+
+fn node(op_type: &str, inputs: &[&str], output: &str, attributes: Vec<AttributeProto>) -> NodeProto {
+    NodeProto {
+        name: output.to_string(),
+        op_type: op_type.to_string(),
+        input: inputs.iter().map(|input| input.to_string()).collect(),
+        output: vec![output.to_string()],
+        attribute: attributes,
+        ..Default::default()
+    }
+}
+
+fn model(name: &str, graph: GraphProto) -> ModelProto {
+    ModelProto {
+        ir_version: IR_VERSION,
+        opset_import: vec![OperatorSetIdProto { domain: String::new(), version: OPSET_VERSION }],
+        producer_name: PRODUCER_NAME.to_string(),
+        graph: Some(GraphProto { name: name.to_string(), ..graph }),
+        ..Default::default()
+    }
+}
+
+
+/// A model that returns its input unchanged.
+pub fn identity_model() -> ModelProto {
+    model("identity", GraphProto {
+        node: vec![node("Identity", &[INPUT_NAME], OUTPUT_NAME, vec![])],
+        input: vec![value_info(INPUT_NAME, &IMAGE_SHAPE)],
+        output: vec![value_info(OUTPUT_NAME, &IMAGE_SHAPE)],
+        ..Default::default()
+    })
+}
+
+/// A single 1x1 convolution that sums the color channels of the input into one output channel.
+pub fn conv_model() -> ModelProto {
+    let output_shape = [IMAGE_SHAPE[0], 1, IMAGE_SHAPE[2], IMAGE_SHAPE[3]];
+    model("conv", GraphProto {
+        node: vec![node("Conv", &[INPUT_NAME, "weights", "bias"], OUTPUT_NAME, vec![])],
+        initializer: vec![
+            float_tensor("weights", &[1, 3, 1, 1], vec![1.0; 3]),
+            float_tensor("bias", &[1], vec![0.0]),
+        ],
+        input: vec![value_info(INPUT_NAME, &IMAGE_SHAPE)],
+        output: vec![value_info(OUTPUT_NAME, &output_shape)],
+        ..Default::default()
+    })
+}
+
+/// The tiny color classifier with the input `[1, 3, CLASSIFIER_INPUT_SIZE, CLASSIFIER_INPUT_SIZE]`
+/// and the class probabilities `[1, 3]` as output.
+pub fn classifier_model() -> ModelProto {
+    let size = CLASSIFIER_INPUT_SIZE as i64;
+    let classes = CLASSIFIER_CLASSES.len() as i64;
+    let mut weights = vec![0.0; (3 * classes) as usize];
+    for channel in 0..3 {
+        weights[channel * classes as usize + channel] = CLASSIFIER_SCALE;
+    }
+
+    model("classifier", GraphProto {
+        node: vec![
+            node("GlobalAveragePool", &[INPUT_NAME], "pooled", vec![]),
+            node("Flatten", &["pooled"], "features", vec![int_attribute("axis", 1)]),
+            node("Gemm", &["features", "weights", "bias"], "logits", vec![]),
+            node("Softmax", &["logits"], OUTPUT_NAME, vec![int_attribute("axis", 1)]),
+        ],
+        initializer: vec![
+            float_tensor("weights", &[3, classes], weights),
+            float_tensor("bias", &[classes], vec![0.0; classes as usize]),
+        ],
+        input: vec![value_info(INPUT_NAME, &[1, 3, size, size])],
+        output: vec![value_info(OUTPUT_NAME, &[1, classes])],
+        ..Default::default()
+    })
+}
+
+/// The output of the classifier computed by hand from the averages of the normalized color channels.
+pub fn classifier_reference(channel_averages: [f32; 3]) -> Vec<f32> {
+    let logits: Vec<f32> = channel_averages.iter().map(|average| average * CLASSIFIER_SCALE).collect();
+    let max_logit = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let exponents: Vec<f32> = logits.iter().map(|logit| (logit - max_logit).exp()).collect();
+    let sum: f32 = exponents.iter().sum();
+    exponents.iter().map(|exponent| exponent / sum).collect()
+}
+
+/// The metadata of the classifier: the input size, no normalization besides scaling to [0, 1] and its labels.
+pub fn classifier_metadata() -> ModelMetadata {
+    ModelMetadata {
+        input_width: CLASSIFIER_INPUT_SIZE,
+        input_height: CLASSIFIER_INPUT_SIZE,
+        mean: [0.0; 3],
+        std: [1.0; 3],
+        labels: Some(CLASSIFIER_LABELS.to_string()),
+        source_url: None,
+        ..ModelMetadata::default()
+    }
+}
+
+
+/// A generated model with an input and the output expected from it.
+pub struct ModelCase {
+    pub name: &'static str,
+    pub model: ModelProto,
+    pub input_shape: Vec<usize>,
+    pub input: Vec<f32>,
+    pub expected: Vec<f32>,
+}
+
+/// The models with their expected outputs: the identity and the convolution with an increasing input and the
+/// classifier with a solid color, whose output is computed with `classifier_reference`.
+pub fn model_cases() -> Vec<ModelCase> {
+    let image_shape: Vec<usize> = IMAGE_SHAPE.iter().map(|dimension| *dimension as usize).collect();
+    let image_size: usize = image_shape.iter().product();
+    let ramp: Vec<f32> = (0..image_size).map(|value| value as f32 / 10.0).collect();
+    let channel_size = image_size / 3;
+    let channel_sums: Vec<f32> = (0..channel_size)
+        .map(|index| ramp[index] + ramp[index + channel_size] + ramp[index + 2 * channel_size])
+        .collect();
+
+    let metadata = classifier_metadata();
+    // a solid color with the channel values 0.2, 0.8 and 0.4 after the normalization
+    let colors = [51, 204, 102];
+
+    vec![
+        ModelCase {
+            name: "identity",
+            model: identity_model(),
+            input_shape: image_shape.clone(),
+            input: ramp.clone(),
+            expected: ramp.clone(),
+        },
+        ModelCase { name: "conv", model: conv_model(), input_shape: image_shape, input: ramp, expected: channel_sums },
+        ModelCase {
+            name: "classifier",
+            model: classifier_model(),
+            input_shape: metadata.input_dimensions().to_vec(),
+            input: metadata.normalize_pixels(|_, _, channel| colors[channel]),
+            expected: classifier_reference([0.2, 0.8, 0.4]),
+        },
+    ]
+}
+
+/// Runs the model cases with the function and panics with the list of the failed cases. The function gets the
+/// case and returns the output of the model.
+pub fn check_models<F, E>(run: F)
+where F: Fn(&ModelCase) -> Result<Vec<f32>, E>, E: std::fmt::Debug {
+    let mut failures = Vec::new();
+    for case in model_cases() {
+        let result = run(&case)
+            .map_err(|error| format!("the model failed: {:?}", error))
+            .and_then(|output| compare_outputs(&output, &case.expected));
+        if let Err(error) = result {
+            failures.push(format!("{}: {}", case.name, error));
+        }
+    }
+    assert!(failures.is_empty(), "The model outputs differ from the expected outputs:\n{}", failures.join("\n"));
+}
+
+fn compare_outputs(output: &[f32], expected: &[f32]) -> Result<(), String> {
+    let differs = output.len() != expected.len()
+        || output.iter().zip(expected).any(|(value, expected_value)| (value - expected_value).abs() > OUTPUT_TOLERANCE);
+    match differs {
+        true => Err(format!("{:?} instead of {:?}", output, expected)),
+        false => Ok(()),
+    }
+}
+
+
+/// Serializes the model to the ONNX protobuf format.
+pub fn to_bytes(model: &ModelProto) -> Vec<u8> {
+    model.encode_to_vec()
+}
+
+/// Runs the model with tract and returns the values of the first output.
+pub fn run_model(model: &ModelProto, input_shape: &[usize], input: &[f32]) -> Result<Vec<f32>, String> {
+    let runnable = tract_onnx::onnx()
+        .model_for_read(&mut to_bytes(model).as_slice())
+        .and_then(|model| model.into_optimized())
+        .and_then(|model| model.into_runnable())
+        .map_err(|error| format!("Could not load the model: {:?}", error))?;
+    let input = Tensor::from_shape(input_shape, input).map_err(|error| format!("Invalid input: {:?}", error))?;
+    let outputs = runnable
+        .run(tvec!(input.into()))
+        .map_err(|error| format!("Could not run the model: {:?}", error))?;
+    outputs[0]
+        .to_array_view::<f32>()
+        .map(|output| output.iter().cloned().collect())
+        .map_err(|error| format!("Could not read the output: {:?}", error))
+}
+
+/// Classifies the image with the classifier using the same preprocessing as the runners.
+/// Returns the score and the 1-based class index of the best result.
+pub fn classify_image(image_path: &str) -> Result<(f32, i32), String> {
+    let metadata = classifier_metadata();
+    let image = image::open(image_path)
+        .map_err(|error| format!("Could not load the image {}: {}", image_path, error))?
+        .to_rgb8();
+    let resized = image::imageops::resize(
+        &image,
+        metadata.input_width,
+        metadata.input_height,
+        image::imageops::FilterType::Triangle,
+    );
+    let input = metadata.normalize_pixels(|x, y, channel| resized[(x, y)][channel]);
+    let output = run_model(&classifier_model(), &metadata.input_dimensions(), &input)?;
+    top_results(output, 1).first().cloned().ok_or_else(|| "The classifier gave no result".to_string())
+}
+
+
+/// Writes the models, the classifier metadata and labels to the folder and returns the written file names.
+/// The expected results of the classifier are computed for the images in `image_folder` if it is given.
+pub fn write_models(folder: &str, image_folder: Option<&str>) -> io::Result<Vec<String>> {
+    fs::create_dir_all(folder)?;
+    let mut written = Vec::new();
+    let mut write = |name: &str, contents: &[u8]| {
+        let path = Path::new(folder).join(name);
+        fs::write(&path, contents)?;
+        written.push(path.to_string_lossy().to_string());
+        Ok::<(), io::Error>(())
+    };
+
+    write(IDENTITY_MODEL, &to_bytes(&identity_model()))?;
+    write(CONV_MODEL, &to_bytes(&conv_model()))?;
+    let classifier_bytes = to_bytes(&classifier_model());
+    write(CLASSIFIER_MODEL, &classifier_bytes)?;
+
+    let labels: Vec<String> = CLASSIFIER_CLASSES
+        .iter()
+        .enumerate()
+        .map(|(index, label)| format!("{};{}", index + 1, label))
+        .collect();
+    write(CLASSIFIER_LABELS, labels.join("\n").as_bytes())?;
+
+    let mut metadata = classifier_metadata();
+    metadata.sha256 = Some(sha256_bytes(&classifier_bytes));
+    if let Some(image_folder) = image_folder {
+        let (expected_top1, expected_scores) = expected_results(image_folder)?;
+        metadata.expected_top1 = expected_top1;
+        metadata.expected_scores = expected_scores;
+    }
+    let sidecar = ModelMetadata::sidecar_path(CLASSIFIER_MODEL);
+    write(&sidecar, metadata.to_json().as_bytes())?;
+
+    Ok(written)
+}
+
+/// The classifier results for the images in the folder, keyed by the image file names.
+fn expected_results(image_folder: &str) -> io::Result<(BTreeMap<String, i32>, BTreeMap<String, f32>)> {
+    let mut expected_top1 = BTreeMap::new();
+    let mut expected_scores = BTreeMap::new();
+    for entry in fs::read_dir(image_folder)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        // files that are not images are skipped
+        if let Ok((score, class)) = classify_image(&path.to_string_lossy()) {
+            expected_top1.insert(name.clone(), class);
+            expected_scores.insert(name, score);
+        }
+    }
+    Ok((expected_top1, expected_scores))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_models() {
+        check_models(|case| run_model(&case.model, &case.input_shape, &case.input));
+    }
+
+    #[test]
+    fn test_classifier_class() {
+        let classifier = model_cases().into_iter().find(|case| case.name == "classifier").unwrap();
+        assert_eq!(classifier.expected.len(), CLASSIFIER_CLASSES.len());
+        // the green channel has the highest value
+        assert_eq!(top_results(classifier.expected, 1)[0].1, 2);
+    }
+}
@@ -0,0 +1,28 @@
+// Writes the synthetic test models to a folder, for example `test-models ../bin/models ../bin/images`.
+
+extern crate test_models;
+
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        println!("Usage: {} <output folder> [<image folder>]", args[0]);
+        println!();
+        println!("The expected results of the classifier are computed for the images in the image folder.");
+        process::exit(64);
+    }
+
+    match test_models::write_models(&args[1], args.get(2).map(|folder| folder.as_str())) {
+        Ok(files) => {
+            for file in files {
+                println!("Wrote {}", file);
+            }
+        }
+        Err(error) => {
+            println!("Could not write the test models: {}", error);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Reference tensors for testing the image preprocessing of the runners: synthetic images (solid colors,
+//! gradients, non-square, grayscale, RGBA and 16-bit images) with the expected normalized input tensor.
+//!
+//! The expected tensors are computed independently of `ModelMetadata::normalize_pixels` from the known RGB value
+//! of each pixel, so they also check the channel order and the tensor layout. The images are chosen so that the
+//! resizing does not change the pixel values: images that are not of the input size have a single solid color,
+//! or are only cropped or padded in the center crop and letterbox resize modes.
+
+use image::{
+    DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, ImageFormat, Luma, Rgb, RgbImage, Rgba, RgbaImage,
+};
+use local_names::{Layout, ModelMetadata, ResizeMode};
+use std::io::Cursor;
+
+/// The allowed absolute difference between the preprocessed and the expected tensor values.
+pub const TOLERANCE: f32 = 1e-4;
+/// The input size of the reference metadata, non-square so that swapped coordinates are noticed.
+pub const REFERENCE_WIDTH: u32 = 8;
+pub const REFERENCE_HEIGHT: u32 = 6;
+
+const SOLID_COLOR: [u8; 3] = [50, 100, 200];
+const BACKGROUND: [u8; 3] = [0, 255, 128];
+/// The square input size for the resize mode cases.
+const RESIZE_INPUT_SIZE: u32 = 4;
+
+
+/// A synthetic image with its expected input tensor with the metadata.
+pub struct ReferenceCase {
+    pub name: String,
+    pub metadata: ModelMetadata,
+    pub image: DynamicImage,
+    pub expected: Vec<f32>,
+}
+
+/// The metadata used with the reference images: ImageNet normalization with both supported layouts.
+pub fn reference_metadata() -> Vec<ModelMetadata> {
+    [Layout::NCHW, Layout::NHWC]
+        .iter()
+        .map(|layout| ModelMetadata {
+            input_width: REFERENCE_WIDTH,
+            input_height: REFERENCE_HEIGHT,
+            layout: *layout,
+            ..ModelMetadata::default()
+        })
+        .collect()
+}
+
+/// The expected tensor for an image of the input size with the RGB value `rgb(x, y)` at each pixel.
+pub fn reference_tensor<F>(metadata: &ModelMetadata, rgb: F) -> Vec<f32>
+where F: Fn(u32, u32) -> [u8; 3] {
+    let (width, height) = (metadata.input_width as usize, metadata.input_height as usize);
+    let mut tensor = vec![0.0; width * height * 3];
+    for y in 0..height {
+        for x in 0..width {
+            for (channel, value) in rgb(x as u32, y as u32).iter().enumerate() {
+                let index = match metadata.layout {
+                    Layout::NCHW => (channel * height + y) * width + x,
+                    Layout::NHWC => (y * width + x) * 3 + channel,
+                };
+                tensor[index] = (*value as f32 / 255.0 - metadata.mean[channel]) / metadata.std[channel];
+            }
+        }
+    }
+    tensor
+}
+
+fn gradient(x: u32, y: u32) -> [u8; 3] {
+    [(x * 30) as u8, (y * 40) as u8, (255 - x * 20 - y * 10) as u8]
+}
+
+fn gray_gradient(x: u32, y: u32) -> u8 {
+    (x * 20 + y * 15) as u8
+}
+
+/// Encodes the image as PNG and decodes it back, like an image read from a file.
+fn through_png(image: DynamicImage) -> DynamicImage {
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).expect("could not encode the PNG image");
+    image::load_from_memory_with_format(&bytes, ImageFormat::Png).expect("could not decode the PNG image")
+}
+
+/// The reference images of the color types with their expected tensors for the metadata.
+pub fn color_cases(metadata: &ModelMetadata) -> Vec<ReferenceCase> {
+    let (width, height) = (metadata.input_width, metadata.input_height);
+    let solid = reference_tensor(metadata, |_, _| SOLID_COLOR);
+    let gradient_tensor = reference_tensor(metadata, gradient);
+    let gray_tensor = reference_tensor(metadata, |x, y| [gray_gradient(x, y); 3]);
+    let to_16_bit = |value: u8| value as u16 * 257;
+
+    let cases = vec![
+        ("solid color", DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb(SOLID_COLOR))), solid.clone()),
+        (
+            "larger non-square solid color",
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(width * 5, height * 2 + 1, Rgb(SOLID_COLOR))),
+            solid.clone(),
+        ),
+        (
+            "smaller solid color",
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 2, Rgb(SOLID_COLOR))),
+            solid.clone(),
+        ),
+        (
+            "gradient",
+            DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| Rgb(gradient(x, y)))),
+            gradient_tensor.clone(),
+        ),
+        (
+            "grayscale gradient",
+            DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| Luma([gray_gradient(x, y)]))),
+            gray_tensor.clone(),
+        ),
+        (
+            "opaque grayscale with alpha",
+            DynamicImage::ImageLumaA8(GrayAlphaImage::from_fn(width, height, |x, y| {
+                image::LumaA([gray_gradient(x, y), 255])
+            })),
+            gray_tensor.clone(),
+        ),
+        (
+            "opaque RGBA gradient",
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+                let [red, green, blue] = gradient(x, y);
+                Rgba([red, green, blue, 255])
+            })),
+            gradient_tensor.clone(),
+        ),
+        (
+            "16-bit RGB gradient",
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(width, height, |x, y| {
+                Rgb(gradient(x, y).map(to_16_bit))
+            })),
+            gradient_tensor,
+        ),
+        (
+            "16-bit grayscale gradient",
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+                Luma([to_16_bit(gray_gradient(x, y))])
+            })),
+            gray_tensor,
+        ),
+        (
+            "larger 16-bit RGBA solid color",
+            DynamicImage::ImageRgba16(ImageBuffer::from_pixel(width * 3, height * 3, {
+                let [red, green, blue] = SOLID_COLOR.map(to_16_bit);
+                Rgba([red, green, blue, u16::MAX])
+            })),
+            solid,
+        ),
+    ];
+
+    cases
+        .into_iter()
+        .map(|(name, image, expected)| ReferenceCase {
+            name: name.to_string(),
+            metadata: metadata.clone(),
+            image: through_png(image),
+            expected,
+        })
+        .collect()
+}
+
+/// The reference images for the center crop and letterbox resize modes with a square input.
+pub fn resize_cases(layout: Layout) -> Vec<ReferenceCase> {
+    let size = RESIZE_INPUT_SIZE;
+    let metadata = |resize: ResizeMode| ModelMetadata {
+        input_width: size,
+        input_height: size,
+        layout,
+        resize,
+        resize_shorter_side: Some(size),
+        background: BACKGROUND,
+        ..ModelMetadata::default()
+    };
+    let rgb = |value: [u8; 3]| Rgb(value);
+    // three squares side by side: the image is not resized in the center crop mode and only the middle is used
+    let stripes = RgbImage::from_fn(size * 3, size, |x, _| match x / size {
+        0 => rgb([255, 0, 0]),
+        1 => rgb(SOLID_COLOR),
+        _ => rgb([0, 0, 255]),
+    });
+    let tall_stripes = RgbImage::from_fn(size, size * 3, |_, y| match y / size {
+        1 => rgb(SOLID_COLOR),
+        _ => rgb([255, 255, 255]),
+    });
+    // the solid image of twice the width of the input is resized to half the height in the letterbox mode
+    let letterbox_metadata = metadata(ResizeMode::Letterbox);
+    let letterbox_wide = reference_tensor(&letterbox_metadata, |_, y| match y {
+        1 | 2 => SOLID_COLOR,
+        _ => BACKGROUND,
+    });
+    let letterbox_tall = reference_tensor(&letterbox_metadata, |x, _| match x {
+        1 | 2 => SOLID_COLOR,
+        _ => BACKGROUND,
+    });
+    let crop_metadata = metadata(ResizeMode::CenterCrop);
+    let solid = reference_tensor(&crop_metadata, |_, _| SOLID_COLOR);
+
+    let cases = vec![
+        ("center crop of a wide image", crop_metadata.clone(), DynamicImage::ImageRgb8(stripes), solid.clone()),
+        ("center crop of a tall image", crop_metadata.clone(), DynamicImage::ImageRgb8(tall_stripes), solid.clone()),
+        (
+            "center crop of a larger image",
+            crop_metadata,
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(size * 5, size * 3, rgb(SOLID_COLOR))),
+            solid,
+        ),
+        (
+            "letterbox of a wide image",
+            letterbox_metadata.clone(),
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(size * 4, size * 2, rgb(SOLID_COLOR))),
+            letterbox_wide,
+        ),
+        (
+            "letterbox of a tall image",
+            letterbox_metadata,
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(size, size * 2, rgb(SOLID_COLOR))),
+            letterbox_tall,
+        ),
+    ];
+
+    cases
+        .into_iter()
+        .map(|(name, metadata, image, expected)| ReferenceCase {
+            name: name.to_string(),
+            metadata,
+            image: through_png(image),
+            expected,
+        })
+        .collect()
+}
+
+/// All the reference cases: the color cases with the reference metadata and the resize mode cases.
+pub fn reference_cases() -> Vec<ReferenceCase> {
+    let mut cases: Vec<ReferenceCase> = reference_metadata().iter().flat_map(color_cases).collect();
+    cases.extend(resize_cases(Layout::NCHW));
+    cases.extend(resize_cases(Layout::NHWC));
+    cases
+}
+
+/// Compares the tensor to the expected one. The error tells the number of the differing values and the first
+/// differing index, or the wrong tensor size.
+pub fn compare_tensors(tensor: &[f32], expected: &[f32]) -> Result<(), String> {
+    if tensor.len() != expected.len() {
+        return Err(format!("the tensor has {} values instead of {}", tensor.len(), expected.len()));
+    }
+    let differences: Vec<usize> = (0..tensor.len())
+        .filter(|index| (tensor[*index] - expected[*index]).abs() > TOLERANCE)
+        .collect();
+    match differences.first() {
+        Some(index) => Err(format!(
+            "{} values differ, the first at index {}: {} instead of {}",
+            differences.len(), index, tensor[*index], expected[*index]
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Runs the preprocessing function with all the reference cases and panics with the list of
+/// the failed cases. The function gets the decoded image and the metadata and returns the input tensor data.
+pub fn check_preprocessing<F, E>(preprocess: F)
+where F: Fn(&DynamicImage, &ModelMetadata) -> Result<Vec<f32>, E>, E: std::fmt::Debug {
+    let mut failures = Vec::new();
+    for case in reference_cases() {
+        let result = preprocess(&case.image, &case.metadata)
+            .map_err(|error| format!("the preprocessing failed: {:?}", error))
+            .and_then(|tensor| compare_tensors(&tensor, &case.expected));
+        if let Err(error) = result {
+            failures.push(format!("{} ({:?}): {}", case.name, case.metadata.layout, error));
+        }
+    }
+    assert!(failures.is_empty(), "The preprocessing differs from the reference tensors:\n{}", failures.join("\n"));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_tensor_matches_normalize_pixels() {
+        for metadata in reference_metadata() {
+            let expected = reference_tensor(&metadata, gradient);
+            let data = metadata.normalize_pixels(|x, y, channel| gradient(x, y)[channel]);
+            assert_eq!(compare_tensors(&data, &expected), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_layouts_differ() {
+        let metadata = reference_metadata();
+        assert_ne!(reference_tensor(&metadata[0], gradient), reference_tensor(&metadata[1], gradient));
+    }
+
+    #[test]
+    fn test_compare_tensors() {
+        assert!(compare_tensors(&[1.0, 2.0], &[1.0]).is_err());
+        assert!(compare_tensors(&[1.0, 2.0], &[1.0, 2.1]).is_err());
+        assert_eq!(compare_tensors(&[1.0, 2.0], &[1.0, 2.0 + TOLERANCE / 2.0]), Ok(()));
+    }
+}
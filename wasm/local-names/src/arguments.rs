@@ -0,0 +1,64 @@
+/// The command line arguments of the inference test programs when they are run as WASI commands.
+#[derive(Debug, Clone)]
+pub struct Arguments {
+    pub model: String,
+    pub image: String,
+    pub repeats: u32,
+    pub options: Vec<(String, Option<String>)>,
+}
+
+
+impl Arguments {
+    /// Parses the arguments (including the program name): model path, image path, number of repeats and options.
+    /// The options are given as `--name` or `--name=value` and only the names in `allowed_options` are accepted.
+    pub fn parse(args: &[String], allowed_options: &[&str]) -> Result<Arguments, String> {
+        let mut positional: Vec<&str> = Vec::new();
+        let mut options: Vec<(String, Option<String>)> = Vec::new();
+
+        for arg in args.iter().skip(1) {
+            if let Some(option) = arg.strip_prefix("--") {
+                let (name, value) = match option.find('=') {
+                    Some(index) => (&option[..index], Some(option[index + 1..].to_string())),
+                    None => (option, None),
+                };
+                if !allowed_options.contains(&name) {
+                    return Err(format!("Unknown option: --{}", name));
+                }
+                options.push((name.to_string(), value));
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        if positional.len() != 3 {
+            return Err(format!("Expected 3 positional arguments, got {}", positional.len()));
+        }
+        let repeats: u32 = match positional[2].parse() {
+            Ok(repeats) => repeats,
+            Err(_) => return Err(format!("Invalid number of repeats: {}", positional[2])),
+        };
+
+        Ok(Arguments {
+            model: positional[0].to_string(),
+            image: positional[1].to_string(),
+            repeats,
+            options,
+        })
+    }
+
+    /// Returns the value of the last occurrence of the given option, or an empty string for an option without a value.
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option_name, _)| option_name == name)
+            .map(|(_, value)| value.as_ref().map_or("", |value| value.as_str()))
+    }
+
+    pub fn usage(program: &str, allowed_options: &[&str]) -> String {
+        let options: Vec<String> = allowed_options.iter().map(|name| format!("[--{}[=<value>]]", name)).collect();
+        format!("Usage: {} <model> <image> <number of repeats> {}", program, options.join(" "))
+            .trim_end()
+            .to_string()
+    }
+}
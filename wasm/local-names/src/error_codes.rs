@@ -0,0 +1,215 @@
+use std::{error::Error, fmt, sync::Arc};
+
+
+/// The errors of the inference test programs. The programs return these as negative error codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorType {
+    SessionCreation,
+    Optimization,
+    Threads,
+    ModelLoad,
+    ImageLoad,
+    ImageConversion,
+    ModelRun,
+    TensorExtract,
+    NoResult,
+    MissingImageName,
+    ListingMismatch,
+    InvalidOutput,
+    InvalidArgument,
+}
+
+const ERROR_TYPES: [ErrorType; 13] = [
+    ErrorType::SessionCreation,
+    ErrorType::Optimization,
+    ErrorType::Threads,
+    ErrorType::ModelLoad,
+    ErrorType::ImageLoad,
+    ErrorType::ImageConversion,
+    ErrorType::ModelRun,
+    ErrorType::TensorExtract,
+    ErrorType::NoResult,
+    ErrorType::MissingImageName,
+    ErrorType::ListingMismatch,
+    ErrorType::InvalidOutput,
+    ErrorType::InvalidArgument,
+];
+
+
+impl ErrorType {
+    pub fn code(self) -> i32 {
+        match self {
+            ErrorType::SessionCreation => -1,
+            ErrorType::Optimization => -2,
+            ErrorType::Threads => -3,
+            ErrorType::ModelLoad => -4,
+            ErrorType::ImageLoad => -5,
+            ErrorType::ImageConversion => -6,
+            ErrorType::ModelRun => -7,
+            ErrorType::TensorExtract => -8,
+            ErrorType::NoResult => -9,
+            ErrorType::MissingImageName => -10,
+            ErrorType::ListingMismatch => -11,
+            ErrorType::InvalidOutput => -12,
+            ErrorType::InvalidArgument => -13,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<ErrorType> {
+        ERROR_TYPES.iter().find(|error| error.code() == code).cloned()
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorType::SessionCreation => "could not create the inference session",
+            ErrorType::Optimization => "could not optimize the model",
+            ErrorType::Threads => "could not set the number of threads",
+            ErrorType::ModelLoad => "could not load the model",
+            ErrorType::ImageLoad => "could not load the image",
+            ErrorType::ImageConversion => "could not convert the image to an input tensor",
+            ErrorType::ModelRun => "could not run the model",
+            ErrorType::TensorExtract => "could not extract the output tensor",
+            ErrorType::NoResult => "the model produced no result",
+            ErrorType::MissingImageName => "missing or invalid arguments",
+            ErrorType::ListingMismatch => "the model and image listings of the host and the guest differ",
+            ErrorType::InvalidOutput => "the model output contains NaN or infinite values",
+            ErrorType::InvalidArgument => "invalid option value",
+        }
+    }
+}
+
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} ({}): {}", self, self.code(), self.description())
+    }
+}
+
+
+pub fn get_error_code(error: ErrorType) -> i32 {
+    error.code()
+}
+
+/// Converts a value returned by the inference to a process exit code:
+/// the absolute value for the (negative) error codes and 0 for the results.
+pub fn get_exit_code(result: i32) -> i32 {
+    match result < 0 {
+        true => result.saturating_neg(),
+        false => 0,
+    }
+}
+
+
+/// An error of the inference test programs with its error type, a message giving the context and the underlying
+/// error from the inference library, the image library or the host, for example
+/// `RunnerError::with_source(ErrorType::ModelRun, "Could not run the model", error)`.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{message}")]
+pub struct RunnerError {
+    kind: ErrorType,
+    message: String,
+    #[source]
+    source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl RunnerError {
+    pub fn new<M: Into<String>>(kind: ErrorType, message: M) -> Self {
+        RunnerError { kind, message: message.into(), source: None }
+    }
+
+    pub fn with_source<M, E>(kind: ErrorType, message: M, source: E) -> Self
+    where M: Into<String>, E: Into<Box<dyn Error + Send + Sync>> {
+        RunnerError { kind, message: message.into(), source: Some(Arc::from(source.into())) }
+    }
+
+    pub fn kind(&self) -> ErrorType {
+        self.kind
+    }
+
+    pub fn code(&self) -> i32 {
+        self.kind.code()
+    }
+
+    /// The process exit code for the error: the absolute value of the error code.
+    pub fn exit_code(&self) -> i32 {
+        get_exit_code(self.code())
+    }
+
+    /// The message and the underlying errors on one line, `None` if the error has only the description of its type.
+    pub fn details(&self) -> Option<String> {
+        let causes = self.causes();
+        match (self.message == self.kind.description(), causes.is_empty()) {
+            (true, true) => None,
+            _ => Some(vec![self.message.clone()].into_iter().chain(causes).collect::<Vec<String>>().join(": ")),
+        }
+    }
+
+    /// The error type, the message and the chain of the underlying errors, one cause on each line.
+    pub fn report(&self) -> String {
+        let mut lines = match self.message == self.kind.description() {
+            true => vec![self.kind.to_string()],
+            false => vec![format!("{}: {}", self.kind, self.message)],
+        };
+        lines.extend(self.causes().into_iter().map(|cause| format!("  caused by: {}", cause)));
+        lines.join("\n")
+    }
+
+    /// The messages of the underlying errors, the direct cause first.
+    fn causes(&self) -> Vec<String> {
+        let mut causes = Vec::new();
+        let mut source = Error::source(self);
+        while let Some(error) = source {
+            causes.push(error.to_string());
+            source = error.source();
+        }
+        causes
+    }
+}
+
+impl From<ErrorType> for RunnerError {
+    fn from(kind: ErrorType) -> Self {
+        RunnerError::new(kind, kind.description())
+    }
+}
+
+/// Converts the errors of other libraries to `RunnerError`s with the error type and the message,
+/// for example `image::open(path).error_context(ErrorType::ImageLoad, || format!("Could not open {}", path))`.
+pub trait ErrorContext<T> {
+    fn error_context<M, F>(self, kind: ErrorType, message: F) -> Result<T, RunnerError>
+    where M: Into<String>, F: FnOnce() -> M;
+}
+
+impl<T, E> ErrorContext<T> for Result<T, E>
+where E: Into<Box<dyn Error + Send + Sync>> {
+    fn error_context<M, F>(self, kind: ErrorType, message: F) -> Result<T, RunnerError>
+    where M: Into<String>, F: FnOnce() -> M {
+        self.map_err(|error| RunnerError::with_source(kind, message(), error))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_runner_error() {
+        let error = RunnerError::from(ErrorType::ModelRun);
+        assert_eq!((error.code(), error.exit_code()), (-7, 7));
+        assert_eq!(error.details(), None);
+        assert_eq!(error.report(), "ModelRun (-7): could not run the model");
+
+        // the underlying errors are kept as the chain of causes
+        let io_error = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let error = Err::<(), io::Error>(io_error)
+            .error_context(ErrorType::ImageLoad, || "Could not load the image husky.jpg")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorType::ImageLoad);
+        assert_eq!(error.to_string(), "Could not load the image husky.jpg");
+        assert_eq!(error.details().unwrap(), "Could not load the image husky.jpg: no such file");
+        assert_eq!(
+            error.report(),
+            "ImageLoad (-5): could not load the image: Could not load the image husky.jpg\n  caused by: no such file"
+        );
+    }
+}
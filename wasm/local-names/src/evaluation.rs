@@ -0,0 +1,369 @@
+// The accuracy evaluation over a labeled image set: the ground truth file lists the images of a folder with
+// their expected class indexes in the format `<image file>;<class index>`, like the label file. Each image is
+// classified by the runner and the top-1 and top-5 accuracy, the confusion between the classes and the list of
+// the misclassified images are reported.
+
+use arguments::Arguments;
+use error_codes::{ErrorType, RunnerError};
+use model_zoo::{ModelMetadata, ResizeMode, RESIZE_OPTION};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+
+/// The number of best results that are checked for the top-5 accuracy.
+pub const TOP_COUNT: usize = 5;
+/// The command line option of the runners that gives the ground truth file and turns on the evaluation mode.
+pub const EVALUATE_OPTION: &str = "evaluate";
+
+
+/// Returns the `count` highest scores with their 1-based class indexes, the highest score first.
+pub fn top_results<I>(scores: I, count: usize) -> Vec<(f32, i32)>
+where I: IntoIterator<Item = f32> {
+    top_results_from(scores, count, 1)
+}
+
+/// Returns the `count` highest scores with their class indexes starting from `first_class`, the highest score first.
+pub fn top_results_from<I>(scores: I, count: usize, first_class: i32) -> Vec<(f32, i32)>
+where I: IntoIterator<Item = f32> {
+    let mut results: Vec<(f32, i32)> = scores.into_iter().zip(first_class..).collect();
+    // a total order with NaN as the lowest score, the sorting panics if the order is not consistent
+    let sort_key = |score: f32| if score.is_nan() { f32::NEG_INFINITY } else { score };
+    results.sort_by(|(score1, _), (score2, _)| sort_key(*score2).total_cmp(&sort_key(*score1)));
+    results.truncate(count);
+    results
+}
+
+
+/// The ground truth file given with `--evaluate=<ground truth file>`, `None` without the option. The option without
+/// the file is an error instead of evaluating with an empty file name.
+pub fn ground_truth_file(arguments: &Arguments) -> Result<Option<&str>, RunnerError> {
+    match arguments.option(EVALUATE_OPTION) {
+        Some(filename) if filename.trim().is_empty() => Err(RunnerError::new(
+            ErrorType::InvalidArgument,
+            format!("The option --{} needs the ground truth file: --{}=<file>", EVALUATE_OPTION, EVALUATE_OPTION),
+        )),
+        filename => Ok(filename),
+    }
+}
+
+/// The resize mode given with `--resize=<mode>` for overriding the resize mode of the model in the evaluation,
+/// `None` without the option. The option is an error without `--evaluate`, since it would have no effect.
+pub fn resize_override(arguments: &Arguments) -> Result<Option<ResizeMode>, RunnerError> {
+    let name = match arguments.option(RESIZE_OPTION) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    if arguments.option(EVALUATE_OPTION).is_none() {
+        return Err(RunnerError::new(
+            ErrorType::InvalidArgument,
+            format!("The option --{} can only be used with --{}", RESIZE_OPTION, EVALUATE_OPTION),
+        ));
+    }
+    ResizeMode::from_name(name).map(Some).ok_or_else(|| {
+        RunnerError::new(
+            ErrorType::InvalidArgument,
+            format!("Unknown resize mode: {} (expected squash, center_crop or letterbox)", name),
+        )
+    })
+}
+
+
+/// Reads the ground truth file with lines in the format `<image file>;<class index>`.
+/// The image files are relative to the image folder and are kept in the order of the file.
+pub fn read_ground_truth(filename: &str) -> Result<Vec<(String, i32)>, String> {
+    let contents = fs::read_to_string(filename)
+        .map_err(|error| format!("Could not read the ground truth file {}: {}", filename, error))?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.rsplit_once(';') {
+            Some((image, class)) => class
+                .trim()
+                .parse::<i32>()
+                .map(|class| (image.trim().to_string(), class))
+                .map_err(|_| format!("Invalid class index in {}: {}", filename, line)),
+            None => Err(format!("Invalid ground truth line in {}: {}", filename, line)),
+        })
+        .collect()
+}
+
+
+/// The result of classifying one image in the evaluation.
+#[derive(Debug, Clone)]
+pub struct EvaluationEntry {
+    pub image: String,
+    pub expected: i32,
+    /// The best classes with the highest score first, or the error from the classification.
+    pub result: Result<Vec<i32>, RunnerError>,
+}
+
+impl EvaluationEntry {
+    pub fn top1(&self) -> Option<i32> {
+        self.result.as_ref().ok().and_then(|classes| classes.first().cloned())
+    }
+
+    pub fn is_top1_correct(&self) -> bool {
+        self.top1() == Some(self.expected)
+    }
+
+    pub fn is_top5_correct(&self) -> bool {
+        self.result
+            .as_ref()
+            .is_ok_and(|classes| classes.iter().take(TOP_COUNT).any(|class| *class == self.expected))
+    }
+}
+
+
+/// The results of an accuracy evaluation.
+#[derive(Debug, Default)]
+pub struct Evaluation {
+    entries: Vec<EvaluationEntry>,
+}
+
+impl Evaluation {
+    /// Classifies all the images listed in the ground truth file. The classification function gets the path of
+    /// the image and returns the best classes with the highest score first.
+    pub fn run<F>(image_folder: &str, ground_truth_file: &str, mut classify: F) -> Result<Evaluation, String>
+    where F: FnMut(&str) -> Result<Vec<i32>, RunnerError> {
+        let ground_truth = read_ground_truth(ground_truth_file)?;
+        let mut evaluation = Evaluation::default();
+        for (image, expected) in ground_truth {
+            let image_path = Path::new(image_folder).join(&image).to_string_lossy().replace("\\", "/");
+            let result = classify(&image_path);
+            evaluation.add(EvaluationEntry { image: image_path, expected, result });
+        }
+        Ok(evaluation)
+    }
+
+    pub fn add(&mut self, entry: EvaluationEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[EvaluationEntry] {
+        &self.entries
+    }
+
+    /// The error of the first image that failed.
+    pub fn first_error(&self) -> Option<&RunnerError> {
+        self.entries.iter().find_map(|entry| entry.result.as_ref().err())
+    }
+
+    /// The number of correct top-1 and top-5 results.
+    pub fn correct_counts(&self) -> (usize, usize) {
+        (
+            self.entries.iter().filter(|entry| entry.is_top1_correct()).count(),
+            self.entries.iter().filter(|entry| entry.is_top5_correct()).count(),
+        )
+    }
+
+    /// The number of images for each pair of the expected class and the top-1 class (`None` for failed images).
+    pub fn confusion(&self) -> BTreeMap<(i32, Option<i32>), usize> {
+        let mut confusion = BTreeMap::new();
+        for entry in self.entries.iter() {
+            *confusion.entry((entry.expected, entry.top1())).or_insert(0) += 1;
+        }
+        confusion
+    }
+
+    /// The report of the evaluation, the labels are used for the class names when they are available.
+    pub fn report(&self, labels: &HashMap<i32, String>) -> String {
+        let class_name = |class: i32| match labels.get(&class) {
+            Some(label) => format!("{} ({})", class, label),
+            None => class.to_string(),
+        };
+        let percentage = |count: usize| match self.entries.is_empty() {
+            true => 0.0,
+            false => 100.0 * count as f64 / self.entries.len() as f64,
+        };
+
+        let total = self.entries.len();
+        let failed = self.entries.iter().filter(|entry| entry.result.is_err()).count();
+        let (top1_correct, top5_correct) = self.correct_counts();
+        let mut lines = vec![
+            format!("Evaluated {} images ({} failed)", total, failed),
+            format!("Top-1 accuracy: {:.2} % ({}/{})", percentage(top1_correct), top1_correct, total),
+            format!("Top-{} accuracy: {:.2} % ({}/{})", TOP_COUNT, percentage(top5_correct), top5_correct, total),
+            String::new(),
+            "Confusion (expected -> top-1: number of images):".to_string(),
+        ];
+        for ((expected, top1), count) in self.confusion() {
+            let top1 = top1.map_or_else(|| "error".to_string(), class_name);
+            lines.push(format!("  {} -> {}: {}", class_name(expected), top1, count));
+        }
+
+        let misclassified: Vec<&EvaluationEntry> =
+            self.entries.iter().filter(|entry| !entry.is_top1_correct()).collect();
+        lines.push(String::new());
+        lines.push(format!("Misclassified images ({}):", misclassified.len()));
+        for entry in misclassified {
+            let result = match &entry.result {
+                Ok(classes) => format!(
+                    "got {}, top-{}: {}",
+                    classes.first().map_or_else(|| "nothing".to_string(), |class| class_name(*class)),
+                    TOP_COUNT,
+                    classes.iter().map(|class| class.to_string()).collect::<Vec<String>>().join(", ")
+                ),
+                Err(error) => match error.details() {
+                    Some(details) => format!("error {:?} ({}): {}", error.kind(), error.code(), details),
+                    None => format!("error {:?} ({})", error.kind(), error.code()),
+                },
+            };
+            lines.push(format!("  {}: expected {}, {}", entry.image, class_name(entry.expected), result));
+        }
+
+        lines.join("\n")
+    }
+}
+
+
+/// Runs the evaluation of the model over the image folder and prints the report with the labels of the model.
+/// The ground truth file or the image folder that could not be read is reported as an `ImageLoad` error. When all
+/// the images fail, the report is printed and the error of the first image is returned as the cause.
+pub fn print_evaluation<F>(
+    model_filename: &str,
+    image_folder: &str,
+    ground_truth_file: &str,
+    classify: F,
+) -> Result<(), RunnerError>
+where F: FnMut(&str) -> Result<Vec<i32>, RunnerError> {
+    let evaluation = Evaluation::run(image_folder, ground_truth_file, classify)
+        .map_err(|error| RunnerError::new(ErrorType::ImageLoad, error))?;
+    let labels = ModelMetadata::for_model(model_filename)
+        .and_then(|metadata| metadata.read_labels())
+        .unwrap_or_default();
+
+    println!("{}", evaluation.report(&labels));
+    match evaluation.first_error() {
+        Some(error) if evaluation.entries().iter().all(|entry| entry.result.is_err()) => Err(RunnerError::with_source(
+            error.kind(),
+            format!("All the {} images failed", evaluation.entries().len()),
+            error.clone(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn arguments(options: &[&str]) -> Arguments {
+        let args: Vec<String> =
+            ["runner", "model.onnx", "images", "1"].iter().chain(options).map(|arg| arg.to_string()).collect();
+        Arguments::parse(&args, &[EVALUATE_OPTION, RESIZE_OPTION]).unwrap()
+    }
+
+    #[test]
+    fn test_resize_override() {
+        assert_eq!(resize_override(&arguments(&[])).unwrap(), None);
+        let evaluate = "--evaluate=ground-truth.txt";
+        assert_eq!(resize_override(&arguments(&[evaluate, "--resize=letterbox"])).unwrap(), Some(ResizeMode::Letterbox));
+
+        let unknown_mode = resize_override(&arguments(&[evaluate, "--resize=stretch"])).unwrap_err();
+        assert_eq!(unknown_mode.kind(), ErrorType::InvalidArgument);
+        let without_evaluation = resize_override(&arguments(&["--resize=center_crop"])).unwrap_err();
+        assert_eq!(without_evaluation.kind(), ErrorType::InvalidArgument);
+        assert_eq!(without_evaluation.to_string(), "The option --resize can only be used with --evaluate");
+    }
+
+    #[test]
+    fn test_ground_truth_file() {
+        assert_eq!(ground_truth_file(&arguments(&[])).unwrap(), None);
+        assert_eq!(ground_truth_file(&arguments(&["--evaluate=labels.txt"])).unwrap(), Some("labels.txt"));
+        for empty in ["--evaluate", "--evaluate=", "--evaluate= "] {
+            let error = ground_truth_file(&arguments(&[empty])).unwrap_err();
+            assert_eq!(error.kind(), ErrorType::InvalidArgument);
+            assert_eq!(error.to_string(), "The option --evaluate needs the ground truth file: --evaluate=<file>");
+        }
+    }
+
+    /// Writes the ground truth file into a temporary folder for the test and returns the folder and the file.
+    fn ground_truth(name: &str, contents: &str) -> (String, String) {
+        let folder = env::temp_dir().join(format!("local-names-{}-{}", name, process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let file = folder.join("ground-truth.txt");
+        fs::write(&file, contents).unwrap();
+        (folder.to_string_lossy().to_string(), file.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn test_read_ground_truth() {
+        let (folder, file) = ground_truth("ground-truth", "husky.jpg;249\n\n  dogs/golden retriever.jpg ; 208 \n");
+        assert_eq!(
+            read_ground_truth(&file).unwrap(),
+            [("husky.jpg".to_string(), 249), ("dogs/golden retriever.jpg".to_string(), 208)]
+        );
+
+        fs::write(&file, "husky.jpg;249\nbigmac.png\n").unwrap();
+        assert_eq!(read_ground_truth(&file).unwrap_err(), format!("Invalid ground truth line in {}: bigmac.png", file));
+        fs::write(&file, "husky.jpg;dog\n").unwrap();
+        assert_eq!(read_ground_truth(&file).unwrap_err(), format!("Invalid class index in {}: husky.jpg;dog", file));
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(read_ground_truth(&file).unwrap_err().starts_with("Could not read the ground truth file"));
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let contents = "husky.jpg;249\nlandrover.jpg;610\nbigmac.png;934\nbroken.jpg;1\n";
+        let (folder, file) = ground_truth("evaluation", contents);
+        let evaluation = Evaluation::run(&folder, &file, |image| match image.rsplit('/').next().unwrap() {
+            "husky.jpg" => Ok(vec![249, 250, 248]),
+            "landrover.jpg" => Ok(vec![818, 610, 657, 628, 868]),
+            "bigmac.png" => Ok(vec![935, 933, 924, 959, 927]),
+            _ => Err(RunnerError::new(ErrorType::ImageLoad, "Could not open broken.jpg")),
+        })
+        .unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(evaluation.entries().len(), 4);
+        assert_eq!(evaluation.entries()[0].image, format!("{}/husky.jpg", folder));
+        // the expected class of bigmac.png is not in the top 5
+        assert_eq!(evaluation.correct_counts(), (1, 2));
+        let confusion: Vec<((i32, Option<i32>), usize)> = evaluation.confusion().into_iter().collect();
+        assert_eq!(confusion, [((1, None), 1), ((249, Some(249)), 1), ((610, Some(818)), 1), ((934, Some(935)), 1)]);
+        assert_eq!(evaluation.first_error().unwrap().kind(), ErrorType::ImageLoad);
+
+        let labels: HashMap<i32, String> =
+            [(249, "malamute"), (818, "sports car")].iter().map(|(class, label)| (*class, label.to_string())).collect();
+        let report = evaluation.report(&labels);
+        let expected_lines = [
+            "Evaluated 4 images (1 failed)".to_string(),
+            "Top-1 accuracy: 25.00 % (1/4)".to_string(),
+            "Top-5 accuracy: 50.00 % (2/4)".to_string(),
+            "  1 -> error: 1".to_string(),
+            "  249 (malamute) -> 249 (malamute): 1".to_string(),
+            "  610 -> 818 (sports car): 1".to_string(),
+            "Misclassified images (3):".to_string(),
+            format!("  {}/landrover.jpg: expected 610, got 818 (sports car), top-5: 818, 610, 657, 628, 868", folder),
+            format!("  {}/bigmac.png: expected 934, got 935, top-5: 935, 933, 924, 959, 927", folder),
+            format!("  {}/broken.jpg: expected 1, error ImageLoad (-5): Could not open broken.jpg", folder),
+        ];
+        for line in expected_lines.iter() {
+            assert!(report.lines().any(|report_line| report_line == line), "{} not in the report:\n{}", line, report);
+        }
+        assert!(!report.contains("husky.jpg"));
+    }
+
+    #[test]
+    fn test_print_evaluation() {
+        let (folder, file) = ground_truth("print-evaluation", "husky.jpg;249\nbroken.jpg;1\n");
+        let image_error = || RunnerError::new(ErrorType::ImageLoad, "Could not open the image");
+
+        let some_failed = print_evaluation("model.onnx", &folder, &file, |image| match image.ends_with("husky.jpg") {
+            true => Ok(vec![249]),
+            false => Err(image_error()),
+        });
+        assert!(some_failed.is_ok());
+
+        let error = print_evaluation("model.onnx", &folder, &file, |_| Err(image_error())).unwrap_err();
+        assert_eq!(error.kind(), ErrorType::ImageLoad);
+        assert_eq!(error.details().unwrap(), "All the 2 images failed: Could not open the image");
+
+        fs::remove_dir_all(&folder).unwrap();
+        let missing_file = print_evaluation("model.onnx", &folder, &file, |_| Ok(vec![1])).unwrap_err();
+        assert_eq!(missing_file.kind(), ErrorType::ImageLoad);
+    }
+}
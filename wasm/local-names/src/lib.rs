@@ -1,6 +1,32 @@
+extern crate serde;
+extern crate serde_json;
+extern crate sha2;
+extern crate thiserror;
+
+pub mod arguments;
+pub mod error_codes;
+pub mod evaluation;
 pub mod local_names;
+pub mod model_zoo;
+pub mod output_check;
+pub mod string_abi;
 
+pub use error_codes::{ErrorContext, ErrorType, RunnerError};
+pub use arguments::Arguments;
+pub use error_codes::get_error_code;
+pub use error_codes::get_exit_code;
 pub use local_names::get_model_index;
 pub use local_names::get_model_name;
 pub use local_names::get_image_index;
 pub use local_names::get_image_name;
+pub use local_names::get_listing_hash;
+pub use local_names::get_registry;
+pub use local_names::verify_listing_hash;
+pub use local_names::LISTING_HASH_ENV;
+pub use local_names::MANIFEST_FILE;
+pub use local_names::REGISTRY_CONFIG_ENV;
+pub use local_names::{Registry, RegistryBuilder};
+pub use evaluation::{ground_truth_file, resize_override, top_results, top_results_from, Evaluation, EVALUATE_OPTION, TOP_COUNT};
+pub use model_zoo::{sha256_bytes, sha256_file, LabelMapping, Layout, ModelMetadata, ModelZoo, ResizeGeometry, ResizeMode, RESIZE_OPTION};
+pub use output_check::{check_output, validate_output, write_npy, InvalidOutput, DUMP_INPUT_ENV};
+pub use string_abi::{InferenceOptions, InferenceResult};
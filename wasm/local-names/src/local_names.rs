@@ -3,68 +3,536 @@ extern crate glob;
 
 use local_names::glob::glob;
 use local_names::lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 
 const MODEL_FOLDER: &str = "models/";
 const IMAGE_FOLDER: &str = "images/";
+const MODEL_PATTERN: &str = "*.onnx";
+const IMAGE_PATTERN: &str = "*.*";
+const IMAGE_EXTENSIONS: [&str; 6] = ["bmp", "gif", "jpeg", "jpg", "png", "webp"];
 
+/// The optional manifest that lists the models and images in a fixed order instead of the folder listings.
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// The environment variable used by the host to give the hash of its listing to the guest.
+pub const LISTING_HASH_ENV: &str = "LOCAL_NAMES_LISTING_HASH";
+/// The environment variable used by the host to give its registry configuration (as JSON) to the guest.
+pub const REGISTRY_CONFIG_ENV: &str = "LOCAL_NAMES_REGISTRY";
 
-fn get_hashmap(folder: &str, pattern: &str) -> HashMap<String, i32> {
-    let mut output_map = HashMap::new();
-    let file_list: Vec<PathBuf> = match glob(&(folder.to_owned() + pattern)) {
-        Ok(paths) => paths.filter_map(Result::ok).collect(),
-        Err(_) => Vec::new(),
-    };
-    for (index, name) in file_list.iter().enumerate() {
-        if let Some(name_str) = name.to_str() {
-            // Replace backslashes with forward slashes for Windows support
-            output_map.insert(name_str.to_string().replace("\\", "/"), (index + 1) as i32);
+
+/// The contents of the manifest file, for example:
+/// `{"models": ["models/mobilenetv2-10.onnx"], "images": ["images/husky.jpg", "images/landrover.jpg"]}`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    models: Vec<String>,
+    images: Vec<String>,
+}
+
+/// A list of file names with 1-based indexes that can be looked up in both directions.
+#[derive(Debug, Default)]
+pub struct Listing {
+    names: Vec<String>,
+    indexes: HashMap<String, i32>,
+}
+
+impl Listing {
+    /// Creates the listing with the indexes given in the order of the names. The names must be unique.
+    pub fn new(names: Vec<String>) -> Result<Listing, String> {
+        let mut indexes = HashMap::new();
+        for (index, name) in names.iter().enumerate() {
+            if indexes.insert(name.clone(), (index + 1) as i32).is_some() {
+                return Err(format!("Duplicate name in the listing: {}", name));
+            }
         }
+        Ok(Listing { names, indexes })
+    }
+
+    pub fn index(&self, name: &str) -> Option<i32> {
+        self.indexes.get(name).cloned()
+    }
+
+    pub fn name(&self, index: i32) -> Option<&str> {
+        match index >= 1 {
+            true => self.names.get((index - 1) as usize).map(|name| name.as_str()),
+            false => None,
+        }
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
     }
-    output_map
 }
 
-lazy_static! {
-    #[derive(Debug)]
-    static ref MODEL_MAP: HashMap<String, i32> = get_hashmap(MODEL_FOLDER, "*.onnx");
+
+/// Builds a registry of the model and image files. The configuration can also be given as JSON,
+/// for example `{"image_folder": "test-images/", "recursive": true}`, with the missing fields using the defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistryBuilder {
+    model_folder: String,
+    model_pattern: String,
+    model_extensions: Vec<String>,
+    image_folder: String,
+    image_pattern: String,
+    image_extensions: Vec<String>,
+    recursive: bool,
+    manifest: Option<String>,
 }
 
-lazy_static! {
-    static ref IMAGE_MAP: HashMap<String, i32> = get_hashmap(IMAGE_FOLDER, "*.*");
+impl Default for RegistryBuilder {
+    fn default() -> Self {
+        RegistryBuilder {
+            model_folder: MODEL_FOLDER.to_string(),
+            model_pattern: MODEL_PATTERN.to_string(),
+            model_extensions: Vec::new(),
+            image_folder: IMAGE_FOLDER.to_string(),
+            image_pattern: IMAGE_PATTERN.to_string(),
+            image_extensions: IMAGE_EXTENSIONS.iter().map(|extension| extension.to_string()).collect(),
+            recursive: false,
+            manifest: Some(MANIFEST_FILE.to_string()),
+        }
+    }
 }
 
+impl RegistryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|error| format!("Invalid registry configuration: {}", error))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn model_folder(mut self, folder: &str) -> Self {
+        self.model_folder = folder.to_string();
+        self
+    }
+
+    pub fn model_pattern(mut self, pattern: &str) -> Self {
+        self.model_pattern = pattern.to_string();
+        self
+    }
+
+    /// The allowed model file extensions (without the dot), an empty list allows all extensions.
+    pub fn model_extensions(mut self, extensions: &[&str]) -> Self {
+        self.model_extensions = extensions.iter().map(|extension| extension.to_string()).collect();
+        self
+    }
+
+    pub fn image_folder(mut self, folder: &str) -> Self {
+        self.image_folder = folder.to_string();
+        self
+    }
 
-fn get_index(map: &HashMap<String, i32>, name: &str) -> Option<i32> {
-    match map.get(name) {
-        Some(index) => Some(*index),
-        None => None
+    pub fn image_pattern(mut self, pattern: &str) -> Self {
+        self.image_pattern = pattern.to_string();
+        self
+    }
+
+    /// The allowed image file extensions (without the dot), an empty list allows all extensions.
+    pub fn image_extensions(mut self, extensions: &[&str]) -> Self {
+        self.image_extensions = extensions.iter().map(|extension| extension.to_string()).collect();
+        self
+    }
+
+    /// Whether the files are also searched from the subfolders.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// The manifest file that overrides the folder listings when it exists, `None` to always use the folder listings.
+    pub fn manifest(mut self, manifest: Option<&str>) -> Self {
+        self.manifest = manifest.map(|filename| filename.to_string());
+        self
+    }
+
+    /// The folders that the files are searched from.
+    pub fn folders(&self) -> [&str; 2] {
+        [&self.model_folder, &self.image_folder]
+    }
+
+    /// The manifest file if it is configured and exists.
+    pub fn existing_manifest(&self) -> Option<&str> {
+        self.manifest.as_deref().filter(|filename| Path::new(filename).is_file())
+    }
+
+    pub fn build(&self) -> Result<Registry, String> {
+        let manifest = match self.existing_manifest() {
+            Some(filename) => read_manifest(filename)?,
+            None => None,
+        };
+        let (model_names, image_names) = match manifest {
+            Some(manifest) => (manifest.models, manifest.images),
+            None => (
+                get_sorted_names(&self.model_folder, &self.model_pattern, &self.model_extensions, self.recursive),
+                get_sorted_names(&self.image_folder, &self.image_pattern, &self.image_extensions, self.recursive),
+            ),
+        };
+        let models = Listing::new(model_names)?;
+        let images = Listing::new(image_names)?;
+        let hash = get_hash(&models, &images);
+
+        Ok(Registry { models, images, hash })
+    }
+}
+
+
+/// The model and image listings and the hash of their contents.
+#[derive(Debug)]
+pub struct Registry {
+    models: Listing,
+    images: Listing,
+    hash: String,
+}
+
+impl Registry {
+    pub fn models(&self) -> &Listing {
+        &self.models
+    }
+
+    pub fn images(&self) -> &Listing {
+        &self.images
+    }
+
+    /// The hash of the model and image listings that can be compared between the host and the guest.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn model_index(&self, name: &str) -> Option<i32> {
+        self.models.index(name)
+    }
+
+    pub fn model_name(&self, index: i32) -> Option<String> {
+        self.models.name(index).map(|name| name.to_string())
+    }
+
+    pub fn image_index(&self, name: &str) -> Option<i32> {
+        self.images.index(name)
+    }
+
+    pub fn image_name(&self, index: i32) -> Option<String> {
+        self.images.name(index).map(|name| name.to_string())
     }
 }
 
-fn get_name(map: &HashMap<String, i32>, index: i32) -> Option<String> {
-    for (name, &idx) in map.iter() {
-        if idx == index {
-            return Some(name.clone());
+
+fn has_allowed_extension(name: &str, extensions: &[String]) -> bool {
+    extensions.is_empty() || Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(extension)))
+}
+
+fn get_sorted_names(folder: &str, pattern: &str, extensions: &[String], recursive: bool) -> Vec<String> {
+    let mut folder = folder.to_string();
+    if !folder.is_empty() && !folder.ends_with('/') {
+        folder.push('/');
+    }
+    let full_pattern = match recursive {
+        true => folder + "**/" + pattern,
+        false => folder + pattern,
+    };
+    let file_list: Vec<PathBuf> = match glob(&full_pattern) {
+        Ok(paths) => paths.filter_map(Result::ok).filter(|path| path.is_file()).collect(),
+        Err(_) => Vec::new(),
+    };
+    let mut names: Vec<String> = file_list
+        .iter()
+        .filter_map(|name| name.to_str())
+        // Replace backslashes with forward slashes for Windows support
+        .map(|name| name.replace("\\", "/"))
+        .filter(|name| has_allowed_extension(name, extensions))
+        .collect();
+    names.sort();
+    names
+}
+
+fn read_manifest(filename: &str) -> Result<Option<Manifest>, String> {
+    let contents = fs::read_to_string(filename)
+        .map_err(|error| format!("Could not read the manifest {}: {}", filename, error))?;
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|error| format!("Invalid manifest {}: {}", filename, error))
+}
+
+/// The FNV-1a hash of the names in both listings as a hexadecimal string.
+fn get_hash(models: &Listing, images: &Listing) -> String {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for (section, listing) in [("models", models), ("images", images)].iter() {
+        let lines = std::iter::once(*section).chain(listing.names().iter().map(|name| name.as_str()));
+        for line in lines {
+            for byte in line.bytes().chain(std::iter::once(b'\n')) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
         }
     }
-    None
+    format!("{:016x}", hash)
+}
+
+/// The default registry: the configuration given by the host in `REGISTRY_CONFIG_ENV` or the default configuration.
+fn get_default_registry() -> Result<Registry, String> {
+    match env::var(REGISTRY_CONFIG_ENV) {
+        Ok(config) => RegistryBuilder::from_json(&config)?.build(),
+        Err(_) => RegistryBuilder::new().build(),
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: Result<Registry, String> = get_default_registry();
 }
 
 
+/// Returns the default registry, or an error if it could not be built (for example because of an invalid manifest).
+pub fn get_registry() -> Result<&'static Registry, String> {
+    REGISTRY.as_ref().map_err(|error| error.clone())
+}
+
+/// Returns the hash of the model and image listings, or an error if the manifest could not be loaded.
+pub fn get_listing_hash() -> Result<String, String> {
+    get_registry().map(|registry| registry.hash().to_string())
+}
+
+/// Checks that the listing hash matches the hash given by the host in the environment variable `LISTING_HASH_ENV`.
+/// Without the environment variable there is nothing to compare and the check passes.
+pub fn verify_listing_hash() -> Result<(), String> {
+    let expected_hash = match env::var(LISTING_HASH_ENV) {
+        Ok(hash) => hash,
+        Err(_) => return Ok(()),
+    };
+    let hash = get_listing_hash()?;
+    match hash == expected_hash {
+        true => Ok(()),
+        false => Err(format!(
+            "The model and image listings differ between the host (hash {}) and the guest (hash {})",
+            expected_hash, hash
+        )),
+    }
+}
+
 pub fn get_model_index(name: &str) -> Option<i32> {
-    get_index(&MODEL_MAP, name)
+    get_registry().ok().and_then(|registry| registry.model_index(name))
 }
 
 pub fn get_model_name(index: i32) -> Option<String> {
-    get_name(&MODEL_MAP, index)
+    get_registry().ok().and_then(|registry| registry.model_name(index))
 }
 
 pub fn get_image_index(name: &str) -> Option<i32> {
-    get_index(&IMAGE_MAP, name)
+    get_registry().ok().and_then(|registry| registry.image_index(name))
 }
 
 pub fn get_image_name(index: i32) -> Option<String> {
-    get_name(&IMAGE_MAP, index)
+    get_registry().ok().and_then(|registry| registry.image_name(index))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Creates a temporary folder for the test with empty files at the relative paths.
+    fn temp_folder(name: &str, files: &[&str]) -> PathBuf {
+        let folder = env::temp_dir().join(format!("local-names-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        for file in files {
+            let path = folder.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        folder
+    }
+
+    /// The names of the listing relative to the folder.
+    fn relative_names(listing: &Listing, folder: &Path) -> Vec<String> {
+        let prefix = format!("{}/", folder.to_str().unwrap());
+        listing.names().iter().map(|name| name.trim_start_matches(&prefix).to_string()).collect()
+    }
+
+    fn listing(names: &[&str]) -> Listing {
+        Listing::new(names.iter().map(|name| name.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_listing() {
+        let listing = listing(&["images/husky.jpg", "images/bigmac.png"]);
+        assert_eq!(listing.index("images/husky.jpg"), Some(1));
+        assert_eq!(listing.index("images/bigmac.png"), Some(2));
+        assert_eq!(listing.index("images/landrover.jpg"), None);
+        for index in 1..=2 {
+            assert_eq!(listing.index(listing.name(index).unwrap()), Some(index));
+        }
+        for index in [-1, 0, 3, i32::MAX] {
+            assert_eq!(listing.name(index), None);
+        }
+
+        let duplicates = vec!["a.jpg".to_string(), "b.jpg".to_string(), "a.jpg".to_string()];
+        assert_eq!(Listing::new(duplicates).unwrap_err(), "Duplicate name in the listing: a.jpg");
+    }
+
+    #[test]
+    fn test_registry() {
+        let files = ["models/b.onnx", "models/a.onnx", "images/landrover.jpg", "images/bigmac.png", "images/husky.jpg"];
+        let folder = temp_folder("registry", &files);
+        let builder = RegistryBuilder::new()
+            .model_folder(folder.join("models").to_str().unwrap())
+            .image_folder(folder.join("images").to_str().unwrap());
+
+        // without a manifest the files are sorted by name
+        let registry = builder.clone().manifest(None).build().unwrap();
+        assert_eq!(relative_names(registry.models(), &folder), ["models/a.onnx", "models/b.onnx"]);
+        assert_eq!(
+            relative_names(registry.images(), &folder),
+            ["images/bigmac.png", "images/husky.jpg", "images/landrover.jpg"]
+        );
+        let husky = registry.image_name(2).unwrap();
+        assert_eq!(registry.image_index(&husky), Some(2));
+        assert_eq!(registry.model_name(3), None);
+
+        // a manifest that does not exist is ignored
+        let manifest = folder.join(MANIFEST_FILE);
+        let manifest_builder = builder.clone().manifest(manifest.to_str());
+        assert_eq!(manifest_builder.build().unwrap().hash(), registry.hash());
+
+        // the manifest overrides the order of the folder listings
+        fs::write(&manifest, r#"{"models": ["models/b.onnx"], "images": ["images/husky.jpg", "images/bigmac.png"]}"#)
+            .unwrap();
+        let manifest_registry = manifest_builder.build().unwrap();
+        assert_eq!(manifest_registry.models().names(), ["models/b.onnx"]);
+        assert_eq!(manifest_registry.image_index("images/husky.jpg"), Some(1));
+        assert_eq!(manifest_registry.image_name(2).as_deref(), Some("images/bigmac.png"));
+        assert_ne!(manifest_registry.hash(), registry.hash());
+
+        fs::write(&manifest, r#"{"models": [], "images": ["images/husky.jpg", "images/husky.jpg"]}"#).unwrap();
+        assert_eq!(manifest_builder.build().unwrap_err(), "Duplicate name in the listing: images/husky.jpg");
+        fs::write(&manifest, r#"{"models": []}"#).unwrap();
+        assert!(manifest_builder.build().unwrap_err().starts_with("Invalid manifest"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn test_registry_builder_json() {
+        // the missing fields use the defaults
+        assert_eq!(RegistryBuilder::from_json("{}").unwrap().to_json(), RegistryBuilder::new().to_json());
+        let builder = RegistryBuilder::from_json(r#"{"image_folder": "test-images/", "recursive": true}"#).unwrap();
+        assert_eq!(builder.to_json(), RegistryBuilder::new().image_folder("test-images/").recursive(true).to_json());
+        assert_eq!(builder.folders(), [MODEL_FOLDER, "test-images/"]);
+        assert_eq!(RegistryBuilder::from_json(&builder.to_json()).unwrap().to_json(), builder.to_json());
+
+        let builder = RegistryBuilder::from_json(r#"{"image_extensions": [], "manifest": null}"#).unwrap();
+        assert_eq!(builder.to_json(), RegistryBuilder::new().image_extensions(&[]).manifest(None).to_json());
+
+        for invalid in ["", "1", r#"{"image_folders": "test-images/"}"#, r#"{"recursive": "yes"}"#] {
+            let error = RegistryBuilder::from_json(invalid).unwrap_err();
+            assert!(error.starts_with("Invalid registry configuration: "), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_registry_builder_build() {
+        let files = [
+            "models/a.onnx",
+            "models/notes.txt",
+            "models/old/b.onnx",
+            "images/husky.jpg",
+            "images/BigMac.PNG",
+            "images/README.md",
+            "images/cars/landrover.jpg",
+        ];
+        let folder = temp_folder("builder", &files);
+        let json = |options: &str| {
+            format!(
+                r#"{{"model_folder": {}, "image_folder": {}, "manifest": null{}}}"#,
+                serde_json::to_string(folder.join("models").to_str().unwrap()).unwrap(),
+                serde_json::to_string(folder.join("images").to_str().unwrap()).unwrap(),
+                options
+            )
+        };
+        let build = |options: &str| RegistryBuilder::from_json(&json(options)).unwrap().build().unwrap();
+
+        // only the image files with the allowed extensions (in any case) in the folder itself
+        let registry = build("");
+        assert_eq!(relative_names(registry.models(), &folder), ["models/a.onnx"]);
+        assert_eq!(relative_names(registry.images(), &folder), ["images/BigMac.PNG", "images/husky.jpg"]);
+
+        let registry = build(r#", "recursive": true"#);
+        assert_eq!(relative_names(registry.models(), &folder), ["models/a.onnx", "models/old/b.onnx"]);
+        assert_eq!(
+            relative_names(registry.images(), &folder),
+            ["images/BigMac.PNG", "images/cars/landrover.jpg", "images/husky.jpg"]
+        );
+
+        // an empty list allows all the extensions
+        let registry = build(r#", "image_extensions": [], "model_pattern": "*""#);
+        assert_eq!(relative_names(registry.models(), &folder), ["models/a.onnx", "models/notes.txt"]);
+        assert_eq!(
+            relative_names(registry.images(), &folder),
+            ["images/BigMac.PNG", "images/README.md", "images/husky.jpg"]
+        );
+        let registry = build(r#", "image_extensions": ["jpg"], "image_pattern": "h*""#);
+        assert_eq!(relative_names(registry.images(), &folder), ["images/husky.jpg"]);
+
+        // a missing folder gives an empty listing
+        fs::remove_dir_all(&folder).unwrap();
+        let registry = build("");
+        assert!(registry.models().names().is_empty() && registry.images().names().is_empty());
+    }
+
+    #[test]
+    fn test_hash() {
+        let models = listing(&["models/a.onnx"]);
+        let images = listing(&["images/bigmac.png", "images/husky.jpg"]);
+        let hash = get_hash(&models, &images);
+        assert_eq!(hash.len(), 16);
+        assert_eq!(get_hash(&listing(&["models/a.onnx"]), &listing(&["images/bigmac.png", "images/husky.jpg"])), hash);
+
+        // any change in the names, their order or the listing they are in changes the hash
+        let changed_listings = [
+            (listing(&["models/a.onnx"]), listing(&["images/husky.jpg", "images/bigmac.png"])),
+            (listing(&["models/a.onnx"]), listing(&["images/bigmac.png"])),
+            (listing(&["models/b.onnx"]), listing(&["images/bigmac.png", "images/husky.jpg"])),
+            (listing(&["models/a.onnx", "images/bigmac.png"]), listing(&["images/husky.jpg"])),
+            (Listing::default(), Listing::default()),
+        ];
+        for (models, images) in changed_listings.iter() {
+            assert_ne!(get_hash(models, images), hash, "{:?} {:?}", models.names(), images.names());
+        }
+    }
+
+    #[test]
+    fn test_verify_listing_hash() {
+        // the default registry of the working directory, the only test that sets the environment variable
+        let hash = get_listing_hash().unwrap();
+        env::set_var(LISTING_HASH_ENV, &hash);
+        assert_eq!(verify_listing_hash(), Ok(()));
+
+        env::set_var(LISTING_HASH_ENV, "0123456789abcdef");
+        let error = verify_listing_hash().unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "The model and image listings differ between the host (hash 0123456789abcdef) and the guest (hash {})",
+                hash
+            )
+        );
+
+        env::remove_var(LISTING_HASH_ENV);
+        assert_eq!(verify_listing_hash(), Ok(()));
+    }
 }
@@ -0,0 +1,518 @@
+// The metadata of the models read from the sidecar files next to the models: for `models/mobilenetv2-10.onnx`
+// the metadata is in `models/mobilenetv2-10.json`. Models without a sidecar file use the default metadata
+// that matches the MobileNet models. The runners use the metadata for the preprocessing of the input image
+// and for checking the result against the labels and the expected classes of the sample images.
+
+use evaluation::top_results_from;
+use local_names::Registry;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+
+const SIDECAR_EXTENSION: &str = "json";
+const DEFAULT_INPUT_SIZE: u32 = 224;
+const DEFAULT_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const DEFAULT_STD: [f32; 3] = [0.229, 0.224, 0.225];
+const DEFAULT_LABELS: &str = "labels.txt";
+const DEFAULT_SCORE_TOLERANCE: f32 = 0.01;
+const DEFAULT_BACKGROUND: [u8; 3] = [255, 255, 255];
+/// The index of the first label when it cannot be detected from the label file.
+const DEFAULT_LABEL_OFFSET: i32 = 1;
+/// The ratio of the shorter side of the resized image to the input size in the center crop mode (256 / 224).
+const CENTER_CROP_RATIO: f64 = 256.0 / 224.0;
+
+/// The command line option of the runners that overrides the resize mode of the model in the evaluation mode.
+pub const RESIZE_OPTION: &str = "resize";
+
+
+/// The order of the dimensions in the input tensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layout {
+    /// batch, channel, height, width
+    NCHW,
+    /// batch, height, width, channel
+    NHWC,
+}
+
+/// How the image is fitted to the input size of the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeMode {
+    /// The whole image is resized to the input size without preserving the aspect ratio.
+    Squash,
+    /// The shorter side of the image is resized to `resize_shorter_side` preserving the aspect ratio
+    /// and the center of the image is cropped to the input size, like in the ImageNet evaluation.
+    CenterCrop,
+    /// The whole image is resized to fit the input size preserving the aspect ratio
+    /// and the remaining area is filled with the background color.
+    Letterbox,
+}
+
+impl ResizeMode {
+    /// The resize mode by its name in the metadata: `squash`, `center_crop` or `letterbox`.
+    pub fn from_name(name: &str) -> Option<ResizeMode> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+    }
+}
+
+/// The size the image is resized to and the position of the resized image in the input of the model.
+/// A negative offset crops the resized image and a positive offset leaves a border filled with the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResizeGeometry {
+    pub width: u32,
+    pub height: u32,
+    pub offset_x: i64,
+    pub offset_y: i64,
+}
+
+/// How the outputs of a model map to the class indexes of its label file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelMapping {
+    /// The class index of the first output of the model after the background class.
+    pub first_class: i32,
+    /// Whether the first output is a background class that is not in the label file. It is never ranked.
+    pub background_class: bool,
+}
+
+/// The metadata of a model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelMetadata {
+    pub input_width: u32,
+    pub input_height: u32,
+    /// The mean of each color channel (RGB) that is subtracted from the pixel values scaled to [0, 1].
+    pub mean: [f32; 3],
+    /// The standard deviation of each color channel (RGB) that the pixel values are divided with.
+    pub std: [f32; 3],
+    pub layout: Layout,
+    pub resize: ResizeMode,
+    /// The length of the shorter side of the resized image in the center crop mode,
+    /// by default 256 / 224 times the input size.
+    pub resize_shorter_side: Option<u32>,
+    /// The RGB color that the transparent pixels of the images are composited onto.
+    pub background: [u8; 3],
+    /// The label file with lines in the format `<index>;<label>`, relative to the folder of the model.
+    pub labels: Option<String>,
+    /// The index of the first label in the label file (usually 0 or 1), detected from the label file when not given.
+    pub label_offset: Option<i32>,
+    /// Whether the first output of the model is a background class that is not in the label file,
+    /// detected by comparing the number of outputs to the number of labels when not given.
+    pub background_class: Option<bool>,
+    /// The smallest index and the number of the labels in the label file, read by `for_model`.
+    #[serde(skip)]
+    pub label_summary: Option<(i32, usize)>,
+    /// The expected class index of the best result for the sample images, the keys are image file names.
+    pub expected_top1: BTreeMap<String, i32>,
+    /// The expected score of the best result for the sample images, the keys are image file names.
+    pub expected_scores: BTreeMap<String, f32>,
+    /// The allowed relative difference from the expected scores.
+    pub score_tolerance: f32,
+    pub source_url: Option<String>,
+    /// The SHA-256 checksum of the model file as a hexadecimal string, verified before the model is loaded.
+    pub sha256: Option<String>,
+}
+
+impl Default for ModelMetadata {
+    fn default() -> Self {
+        ModelMetadata {
+            input_width: DEFAULT_INPUT_SIZE,
+            input_height: DEFAULT_INPUT_SIZE,
+            mean: DEFAULT_MEAN,
+            std: DEFAULT_STD,
+            layout: Layout::NCHW,
+            resize: ResizeMode::Squash,
+            resize_shorter_side: None,
+            background: DEFAULT_BACKGROUND,
+            labels: Some(DEFAULT_LABELS.to_string()),
+            label_offset: None,
+            background_class: None,
+            label_summary: None,
+            expected_top1: BTreeMap::new(),
+            expected_scores: BTreeMap::new(),
+            score_tolerance: DEFAULT_SCORE_TOLERANCE,
+            source_url: None,
+            sha256: None,
+        }
+    }
+}
+
+impl ModelMetadata {
+    /// The sidecar file for the model: the model file name with the extension `.json`.
+    pub fn sidecar_path(model_filename: &str) -> String {
+        Path::new(model_filename)
+            .with_extension(SIDECAR_EXTENSION)
+            .to_string_lossy()
+            .replace("\\", "/")
+    }
+
+    /// Reads the metadata from the sidecar file of the model, or returns the default metadata if there is no sidecar file.
+    /// The path of the label file is resolved relative to the folder of the model.
+    pub fn for_model(model_filename: &str) -> Result<ModelMetadata, String> {
+        let sidecar_filename = ModelMetadata::sidecar_path(model_filename);
+        let mut metadata = match Path::new(&sidecar_filename).is_file() {
+            true => {
+                let contents = fs::read_to_string(&sidecar_filename)
+                    .map_err(|error| format!("Could not read the model metadata {}: {}", sidecar_filename, error))?;
+                ModelMetadata::from_json(&contents)
+                    .map_err(|error| format!("Invalid model metadata {}: {}", sidecar_filename, error))?
+            }
+            false => ModelMetadata::default(),
+        };
+
+        let model_folder = Path::new(model_filename).parent().unwrap_or_else(|| Path::new(""));
+        metadata.labels = metadata.labels.map(|labels| {
+            model_folder.join(labels).to_string_lossy().replace("\\", "/")
+        });
+        // a missing label file is only reported when the labels are printed
+        metadata.label_summary = metadata
+            .read_labels()
+            .ok()
+            .and_then(|labels| labels.keys().min().map(|first| (*first, labels.len())));
+        Ok(metadata)
+    }
+
+    pub fn from_json(json: &str) -> Result<ModelMetadata, String> {
+        let metadata: ModelMetadata = serde_json::from_str(json).map_err(|error| error.to_string())?;
+        if metadata.input_width == 0 || metadata.input_height == 0 {
+            return Err("The input size must be positive".to_string());
+        }
+        if metadata.score_tolerance < 0.0 {
+            return Err("The score tolerance must not be negative".to_string());
+        }
+        if metadata.std.contains(&0.0) {
+            return Err("The standard deviations must be non-zero".to_string());
+        }
+        if metadata.resize_shorter_side.is_some_and(|side| side < metadata.input_width.max(metadata.input_height)) {
+            return Err("The resized shorter side must not be smaller than the input size".to_string());
+        }
+        Ok(metadata)
+    }
+
+    /// The metadata in the format of the sidecar file.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// The dimensions of the input tensor for a single image.
+    pub fn input_dimensions(&self) -> [usize; 4] {
+        let (width, height) = (self.input_width as usize, self.input_height as usize);
+        match self.layout {
+            Layout::NCHW => [1, 3, height, width],
+            Layout::NHWC => [1, height, width, 3],
+        }
+    }
+
+    /// The size to resize an image of the given size to and its position in the input, according to the resize mode.
+    pub fn resize_geometry(&self, image_width: u32, image_height: u32) -> ResizeGeometry {
+        let (input_width, input_height) = (self.input_width as f64, self.input_height as f64);
+        let (width, height) = (image_width.max(1) as f64, image_height.max(1) as f64);
+        let scale = match self.resize {
+            ResizeMode::Squash => {
+                return ResizeGeometry { width: self.input_width, height: self.input_height, offset_x: 0, offset_y: 0 };
+            }
+            ResizeMode::CenterCrop => {
+                let shorter_side = match self.resize_shorter_side {
+                    Some(side) => side as f64,
+                    None => (input_width.min(input_height) * CENTER_CROP_RATIO).round().max(input_width.max(input_height)),
+                };
+                shorter_side / width.min(height)
+            }
+            ResizeMode::Letterbox => (input_width / width).min(input_height / height),
+        };
+
+        let resized_width = ((width * scale).round() as u32).max(1);
+        let resized_height = ((height * scale).round() as u32).max(1);
+        ResizeGeometry {
+            width: resized_width,
+            height: resized_height,
+            offset_x: (self.input_width as i64 - resized_width as i64).div_euclid(2),
+            offset_y: (self.input_height as i64 - resized_height as i64).div_euclid(2),
+        }
+    }
+
+    /// Creates the normalized input tensor data from an image resized to the size given by the geometry.
+    /// The function `pixel` gives the value of the color channel at (x, y) of the resized image,
+    /// the pixels outside the resized image get the background color.
+    pub fn normalize_resized_pixels<F>(&self, geometry: &ResizeGeometry, pixel: F) -> Vec<f32>
+    where F: Fn(u32, u32, usize) -> u8 {
+        self.normalize_pixels(|x, y, channel| {
+            let resized_x = x as i64 - geometry.offset_x;
+            let resized_y = y as i64 - geometry.offset_y;
+            match (0..geometry.width as i64).contains(&resized_x) && (0..geometry.height as i64).contains(&resized_y) {
+                true => pixel(resized_x as u32, resized_y as u32, channel),
+                false => self.background[channel],
+            }
+        })
+    }
+
+    /// Creates the normalized input tensor data in the layout of the model from an image of the input size.
+    /// The function `pixel` gives the value of the color channel (0: red, 1: green, 2: blue) at (x, y).
+    pub fn normalize_pixels<F>(&self, pixel: F) -> Vec<f32>
+    where F: Fn(u32, u32, usize) -> u8 {
+        let normalize = |x: u32, y: u32, channel: usize| {
+            (pixel(x, y, channel) as f32 / 255.0 - self.mean[channel]) / self.std[channel]
+        };
+
+        let mut data = Vec::with_capacity((self.input_width * self.input_height * 3) as usize);
+        match self.layout {
+            Layout::NCHW => {
+                for channel in 0..3 {
+                    for y in 0..self.input_height {
+                        for x in 0..self.input_width {
+                            data.push(normalize(x, y, channel));
+                        }
+                    }
+                }
+            }
+            Layout::NHWC => {
+                for y in 0..self.input_height {
+                    for x in 0..self.input_width {
+                        for channel in 0..3 {
+                            data.push(normalize(x, y, channel));
+                        }
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    /// Computes the SHA-256 checksum of the model file and compares it to the checksum in the metadata.
+    /// Returns the computed checksum, or an error if it differs from the expected one.
+    /// Without a checksum in the metadata the check passes.
+    pub fn verify_checksum(&self, model_filename: &str) -> Result<String, String> {
+        let checksum = sha256_file(model_filename)?;
+        match &self.sha256 {
+            Some(expected) if !expected.eq_ignore_ascii_case(&checksum) => Err(format!(
+                "The SHA-256 checksum of the model {} is {} but {} was expected",
+                model_filename, checksum, expected
+            )),
+            _ => Ok(checksum),
+        }
+    }
+
+    /// The mapping from the outputs of the model to the class indexes for a model with the given number of outputs.
+    /// The values that are not given in the metadata are detected from the label file. Also returns a warning if
+    /// the metadata, the label file and the number of outputs disagree.
+    pub fn label_mapping(&self, output_count: usize) -> (LabelMapping, Option<String>) {
+        let detected_offset = self.label_summary.map(|(first, _)| first);
+        let detected_background = self.label_summary.map(|(_, count)| output_count == count + 1);
+        let label_offset = self.label_offset.or(detected_offset).unwrap_or(DEFAULT_LABEL_OFFSET);
+        let background_class = self.background_class.or(detected_background).unwrap_or(false);
+
+        let mut warnings = Vec::new();
+        if let (Some(offset), Some(first)) = (self.label_offset, detected_offset) {
+            if offset != first {
+                warnings.push(format!("the label offset {} differs from the first label index {}", offset, first));
+            }
+        }
+        if let Some((_, count)) = self.label_summary {
+            if output_count != count + background_class as usize {
+                warnings.push(format!(
+                    "the model has {} outputs but there are {} labels{}",
+                    output_count, count, if background_class { " and a background class" } else { "" }
+                ));
+            }
+        }
+
+        let mapping = LabelMapping { first_class: label_offset, background_class };
+        let warning = match warnings.is_empty() {
+            true => None,
+            false => Some(warnings.join(", ")),
+        };
+        (mapping, warning)
+    }
+
+    /// Returns the `count` highest scores with their class indexes according to the label mapping, the highest
+    /// score first. The background class is left out, so the results are always classes of the label file.
+    /// The warning about the label mapping is printed when `verbose` is set.
+    pub fn top_results(&self, scores: &[f32], count: usize, verbose: bool) -> Vec<(f32, i32)> {
+        let (mapping, warning) = self.label_mapping(scores.len());
+        if let (true, Some(warning)) = (verbose, warning) {
+            println!("Warning: {}", warning);
+        }
+        let background = mapping.background_class as usize;
+        top_results_from(scores.iter().skip(background).cloned(), count, mapping.first_class)
+    }
+
+    /// The expected class for the image, the image is matched by its file name.
+    pub fn expected_class(&self, image_filename: &str) -> Option<i32> {
+        self.expected_top1.get(file_name(image_filename)).cloned()
+    }
+
+    /// The expected score of the best result for the image, the image is matched by its file name.
+    pub fn expected_score(&self, image_filename: &str) -> Option<f32> {
+        self.expected_scores.get(file_name(image_filename)).cloned()
+    }
+
+    /// Whether the score is within the tolerance of the expected score.
+    pub fn is_score_within_tolerance(&self, expected_score: f32, score: f32) -> bool {
+        (score - expected_score).abs() <= self.score_tolerance * expected_score.abs().max(1.0)
+    }
+
+    /// Reads the labels from the label file of the model, an empty map if the model has no label file.
+    pub fn read_labels(&self) -> Result<HashMap<i32, String>, String> {
+        match &self.labels {
+            Some(filename) => read_labels(filename),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /// Prints the label of the result and whether it matches the expected class for the image.
+    /// Returns `Some(true)` or `Some(false)` if there is an expected class for the image, otherwise `None`.
+    pub fn check_result(&self, image_filename: &str, class: i32) -> Option<bool> {
+        match self.read_labels() {
+            Ok(labels) => {
+                if let Some(label) = labels.get(&class) {
+                    println!("Label: {}", label);
+                }
+            }
+            Err(error) => println!("{}", error),
+        }
+
+        let expected_class = self.expected_class(image_filename)?;
+        match expected_class == class {
+            true => println!("The result matches the expected class {}", expected_class),
+            false => println!("The result does not match the expected class {}", expected_class),
+        }
+        Some(expected_class == class)
+    }
+}
+
+
+fn file_name(filename: &str) -> &str {
+    Path::new(filename).file_name().and_then(|name| name.to_str()).unwrap_or(filename)
+}
+
+/// The SHA-256 checksum of the data as a lowercase hexadecimal string.
+pub fn sha256_bytes(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+/// The SHA-256 checksum of the file as a lowercase hexadecimal string.
+pub fn sha256_file(filename: &str) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    File::open(filename)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .map_err(|error| format!("Could not read the file {}: {}", filename, error))?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+/// Reads a label file with lines in the format `<index>;<label>`.
+pub fn read_labels(filename: &str) -> Result<HashMap<i32, String>, String> {
+    let contents = fs::read_to_string(filename)
+        .map_err(|error| format!("Could not read the label file {}: {}", filename, error))?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.split_once(';') {
+            Some((index, label)) => index
+                .trim()
+                .parse::<i32>()
+                .map(|index| (index, label.trim().to_string()))
+                .map_err(|_| format!("Invalid label index in {}: {}", filename, line)),
+            None => Err(format!("Invalid label line in {}: {}", filename, line)),
+        })
+        .collect()
+}
+
+
+/// The metadata of all the models in a registry.
+#[derive(Debug)]
+pub struct ModelZoo {
+    models: BTreeMap<String, ModelMetadata>,
+}
+
+impl ModelZoo {
+    /// Reads the metadata of all the models in the registry. Fails if any of the sidecar files is invalid.
+    pub fn from_registry(registry: &Registry) -> Result<ModelZoo, String> {
+        let models = registry
+            .models()
+            .names()
+            .iter()
+            .map(|name| ModelMetadata::for_model(name).map(|metadata| (name.clone(), metadata)))
+            .collect::<Result<BTreeMap<String, ModelMetadata>, String>>()?;
+        Ok(ModelZoo { models })
+    }
+
+    pub fn get(&self, model_filename: &str) -> Option<&ModelMetadata> {
+        self.models.get(model_filename)
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = (&String, &ModelMetadata)> {
+        self.models.iter()
+    }
+
+    /// The SHA-256 checksums in the metadata of the models, for verifying a model given as bytes without its file name.
+    pub fn checksums(&self) -> Vec<String> {
+        self.models.values().filter_map(|metadata| metadata.sha256.clone()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_mapping() {
+        // 1000 labels with the indexes 1..=1000 like in labels.txt
+        let mut metadata = ModelMetadata { label_summary: Some((1, 1000)), ..ModelMetadata::default() };
+        let mut scores = vec![0.0; 1000];
+        scores[248] = 1.0;
+        assert_eq!(metadata.label_mapping(1000), (LabelMapping { first_class: 1, background_class: false }, None));
+        assert_eq!(metadata.top_results(&scores, 1, false), vec![(1.0, 249)]);
+
+        // a background class before the 1000 classes is detected from the output count
+        scores.insert(0, 0.0);
+        assert_eq!(metadata.label_mapping(1001), (LabelMapping { first_class: 1, background_class: true }, None));
+        assert_eq!(metadata.top_results(&scores, 1, false), vec![(1.0, 249)]);
+
+        // a 0-based label file
+        metadata.label_summary = Some((0, 1000));
+        scores.remove(0);
+        assert_eq!(metadata.top_results(&scores, 1, false), vec![(1.0, 248)]);
+
+        // the configured values are used as they are, with a warning about the mismatch
+        metadata.label_offset = Some(1);
+        metadata.background_class = Some(true);
+        let (mapping, warning) = metadata.label_mapping(1000);
+        assert_eq!(mapping, LabelMapping { first_class: 1, background_class: true });
+        assert!(warning.unwrap().contains("differs from the first label index 0"));
+    }
+
+    #[test]
+    fn test_background_not_ranked() {
+        let metadata = ModelMetadata { label_summary: Some((1, 3)), ..ModelMetadata::default() };
+        // the background output has the highest score
+        let scores = [0.9, 0.02, 0.05, 0.03];
+        assert_eq!(metadata.top_results(&scores, 1, false), vec![(0.05, 2)]);
+        assert_eq!(metadata.top_results(&scores, 5, false), vec![(0.05, 2), (0.03, 3), (0.02, 1)]);
+    }
+
+    #[test]
+    fn test_resize_geometry() {
+        let mut metadata = ModelMetadata::default();
+        let squash = metadata.resize_geometry(640, 480);
+        assert_eq!(squash, ResizeGeometry { width: 224, height: 224, offset_x: 0, offset_y: 0 });
+
+        // the shorter side is resized to 256 by default and the center 224x224 is cropped
+        metadata.resize = ResizeMode::CenterCrop;
+        let crop = metadata.resize_geometry(640, 480);
+        assert_eq!(crop, ResizeGeometry { width: 341, height: 256, offset_x: -59, offset_y: -16 });
+
+        metadata.resize = ResizeMode::Letterbox;
+        let letterbox = metadata.resize_geometry(640, 480);
+        assert_eq!(letterbox, ResizeGeometry { width: 224, height: 168, offset_x: 0, offset_y: 28 });
+    }
+}
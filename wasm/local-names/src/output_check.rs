@@ -0,0 +1,148 @@
+// The validation of the model outputs: the runners check the output before finding the best scores, report the
+// invalid values and optionally write the input tensor that produced them to a NumPy file for reproducing the failure.
+
+use std::{env, error::Error, fmt, fs, io};
+
+use error_codes::{ErrorType, RunnerError};
+
+
+/// The environment variable with the file that the input tensor is written to when the model output is invalid,
+/// for example `ONNX_DUMP_INPUT=dumps/input.npy`.
+pub const DUMP_INPUT_ENV: &str = "ONNX_DUMP_INPUT";
+/// The number of invalid output values whose positions are reported.
+const REPORTED_POSITIONS: usize = 5;
+
+
+/// The NaN and infinite values found in a model output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidOutput {
+    pub output_count: usize,
+    pub nan_count: usize,
+    pub infinite_count: usize,
+    /// The first invalid values with their positions in the flattened output.
+    pub first_positions: Vec<(usize, f32)>,
+}
+
+impl fmt::Display for InvalidOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let positions: Vec<String> = self
+            .first_positions
+            .iter()
+            .map(|(position, value)| format!("{} ({})", position, value))
+            .collect();
+        write!(
+            f,
+            "the model output has {} NaN and {} infinite values out of {}, first at the positions {}",
+            self.nan_count, self.infinite_count, self.output_count, positions.join(", ")
+        )
+    }
+}
+
+impl Error for InvalidOutput {}
+
+
+/// Checks that all the values of the model output are finite. The runners call this before ranking the scores,
+/// since NaN or infinite scores would give an arbitrary class instead of an error.
+pub fn check_output(output: &[f32]) -> Result<(), InvalidOutput> {
+    let invalid: Vec<(usize, f32)> = output
+        .iter()
+        .cloned()
+        .enumerate()
+        .filter(|(_, value)| !value.is_finite())
+        .collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+
+    let nan_count = invalid.iter().filter(|(_, value)| value.is_nan()).count();
+    Err(InvalidOutput {
+        output_count: output.len(),
+        nan_count,
+        infinite_count: invalid.len() - nan_count,
+        first_positions: invalid.into_iter().take(REPORTED_POSITIONS).collect(),
+    })
+}
+
+/// Checks the model output for NaN and infinite values. If `DUMP_INPUT_ENV` is set, the input tensor
+/// given by `input` is written to the file so that the failing input can be reproduced.
+pub fn validate_output<F>(output: &[f32], input_dimensions: &[usize], input: F) -> Result<(), RunnerError>
+where F: FnOnce() -> Vec<f32> {
+    check_output(output).map_err(|invalid| {
+        if let Ok(filename) = env::var(DUMP_INPUT_ENV) {
+            match write_npy(&filename, input_dimensions, &input()) {
+                Ok(()) => println!("The input tensor was written to {}", filename),
+                Err(error) => println!("Could not write the input tensor to {}: {}", filename, error),
+            }
+        }
+        RunnerError::with_source(ErrorType::InvalidOutput, "The model output is invalid", invalid)
+    })
+}
+
+/// Writes the tensor to a NumPy `.npy` file as little-endian 32-bit floats in the C order.
+pub fn write_npy(filename: &str, dimensions: &[usize], data: &[f32]) -> io::Result<()> {
+    fs::write(filename, npy_bytes(dimensions, data))
+}
+
+/// The contents of a version 1.0 `.npy` file: the magic string, the version, the header length,
+/// the header padded with spaces to a multiple of 64 bytes and the data.
+fn npy_bytes(dimensions: &[usize], data: &[f32]) -> Vec<u8> {
+    let shape: Vec<String> = dimensions.iter().map(|dimension| dimension.to_string()).collect();
+    let shape = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!("({})", shape.join(", ")),
+    };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape);
+    const PREFIX_LENGTH: usize = 10;
+    let padding = 63 - (PREFIX_LENGTH + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for value in data {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evaluation::top_results;
+
+    #[test]
+    fn test_output_check() {
+        let mut output: Vec<f32> = (0..10).map(|value| value as f32).collect();
+        assert_eq!(check_output(&output), Ok(()));
+        output[3] = f32::NAN;
+        output[7] = f32::INFINITY;
+        output[8] = f32::NEG_INFINITY;
+
+        let invalid = check_output(&output).unwrap_err();
+        assert_eq!((invalid.output_count, invalid.nan_count, invalid.infinite_count), (10, 1, 2));
+        assert_eq!(invalid.first_positions.iter().map(|(position, _)| *position).collect::<Vec<usize>>(), vec![3, 7, 8]);
+        assert!(invalid.to_string().contains("first at the positions 3 (NaN), 7 (inf), 8 (-inf)"));
+        let error = validate_output(&output, &[1, 10], Vec::new).unwrap_err();
+        assert_eq!(error.kind(), ErrorType::InvalidOutput);
+        assert!(error.report().contains("caused by: the model output has 1 NaN and 2 infinite values"));
+        // the sorting of the scores does not panic on NaN
+        assert_eq!(top_results(output, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_write_npy() {
+        let path = env::temp_dir().join(format!("local-names-{}.npy", std::process::id()));
+        write_npy(&path.to_string_lossy(), &[1, 2], &[1.0, -2.5]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let header_length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_length]).unwrap();
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!((10 + header_length) % 64, 0);
+        assert_eq!(header.trim_end(), "{'descr': '<f4', 'fortran_order': False, 'shape': (1, 2), }");
+        assert_eq!(&bytes[10 + header_length..], [1.0f32.to_le_bytes(), (-2.5f32).to_le_bytes()].concat().as_slice());
+    }
+}
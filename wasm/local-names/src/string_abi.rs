@@ -0,0 +1,210 @@
+// The string passing ABI between the host and a core Wasm guest: the host allocates memory from the guest
+// with the exported allocator, writes the UTF-8 model and image paths and a JSON options blob there, and the
+// guest writes a JSON result back to its memory and returns the location of it packed into a single u64.
+
+use error_codes::{ErrorType, RunnerError};
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::alloc::{self, Layout};
+use std::slice;
+
+
+/// The name of the exported allocation function: `(size: i32) -> i32`.
+pub const ALLOCATE_FUNCTION_NAME: &str = "allocate";
+/// The name of the exported deallocation function: `(pointer: i32, size: i32)`.
+pub const DEALLOCATE_FUNCTION_NAME: &str = "deallocate";
+/// The name of the exported inference function:
+/// `(model_pointer, model_length, image_pointer, image_length, options_pointer, options_length: i32) -> i64`.
+pub const INFERENCE_FUNCTION_NAME: &str = "run_inference_json";
+
+const DEFAULT_REPEATS: u32 = 10;
+
+
+/// The options given to the inference as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InferenceOptions {
+    #[serde(default = "default_repeats")]
+    pub repeats: u32,
+}
+
+fn default_repeats() -> u32 {
+    DEFAULT_REPEATS
+}
+
+impl Default for InferenceOptions {
+    fn default() -> Self {
+        InferenceOptions { repeats: DEFAULT_REPEATS }
+    }
+}
+
+impl InferenceOptions {
+    /// Parses the options from JSON, an empty string gives the default options.
+    pub fn from_json(json: &str) -> Result<InferenceOptions, String> {
+        match json.trim().is_empty() {
+            true => Ok(InferenceOptions::default()),
+            false => serde_json::from_str(json).map_err(|error| format!("Invalid options: {}", error)),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+
+/// An error from the inference in the JSON result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InferenceError {
+    pub code: i32,
+    pub name: String,
+    pub description: String,
+    pub message: Option<String>,
+}
+
+/// The result of the inference written back as JSON: either the class and score or an error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InferenceResult {
+    pub model: String,
+    pub image: String,
+    pub class: Option<i32>,
+    pub score: Option<f32>,
+    pub error: Option<InferenceError>,
+}
+
+impl InferenceResult {
+    pub fn new(model: &str, image: &str, result: Result<(f32, i32), RunnerError>) -> Self {
+        let (class, score, error) = match result {
+            Ok((score, class)) => (Some(class), Some(score), None),
+            Err(error) => (None, None, Some(InferenceError::new(error.kind(), error.details()))),
+        };
+        InferenceResult { model: model.to_string(), image: image.to_string(), class, score, error }
+    }
+
+    pub fn from_error(model: &str, image: &str, error: ErrorType, message: String) -> Self {
+        InferenceResult {
+            model: model.to_string(),
+            image: image.to_string(),
+            class: None,
+            score: None,
+            error: Some(InferenceError::new(error, Some(message))),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<InferenceResult, String> {
+        serde_json::from_str(json).map_err(|error| format!("Invalid result: {}", error))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// The class index for a successful inference or the negative error code.
+    pub fn code(&self) -> i32 {
+        match (&self.error, self.class) {
+            (Some(error), _) => error.code,
+            (None, Some(class)) => class,
+            (None, None) => ErrorType::NoResult.code(),
+        }
+    }
+}
+
+impl InferenceError {
+    pub fn new(error: ErrorType, message: Option<String>) -> Self {
+        InferenceError {
+            code: error.code(),
+            name: format!("{:?}", error),
+            description: error.description().to_string(),
+            message,
+        }
+    }
+}
+
+
+/// Packs a pointer and a length into the u64 returned by the inference function.
+pub fn pack_pointer(pointer: u32, length: u32) -> u64 {
+    ((pointer as u64) << 32) | (length as u64)
+}
+
+/// Unpacks the pointer and the length from the u64 returned by the inference function.
+pub fn unpack_pointer(packed: u64) -> (u32, u32) {
+    ((packed >> 32) as u32, packed as u32)
+}
+
+
+fn layout(size: usize) -> Layout {
+    // zero sized allocations are not allowed, so at least one byte is always allocated
+    Layout::array::<u8>(size.max(1)).unwrap_or_else(|_| Layout::new::<u8>())
+}
+
+/// Allocates memory from the guest for the host. Used by the exported allocator of the guest.
+pub fn allocate(size: usize) -> *mut u8 {
+    unsafe { alloc::alloc(layout(size)) }
+}
+
+/// Frees memory that was allocated with `allocate`. Used by the exported deallocator of the guest.
+///
+/// # Safety
+/// The pointer must have been returned by `allocate` with the same size and not freed before.
+pub unsafe fn deallocate(pointer: *mut u8, size: usize) {
+    alloc::dealloc(pointer, layout(size))
+}
+
+/// Reads an UTF-8 string that the host has written to the guest memory.
+///
+/// # Safety
+/// The pointer must point to `length` readable bytes.
+pub unsafe fn read_string(pointer: *const u8, length: usize) -> Result<String, String> {
+    match length {
+        0 => Ok(String::new()),
+        _ => String::from_utf8(slice::from_raw_parts(pointer, length).to_vec())
+            .map_err(|error| format!("Invalid UTF-8 string: {}", error)),
+    }
+}
+
+/// Copies the string to newly allocated guest memory and returns its packed pointer and length.
+/// The host frees the memory with the exported deallocator.
+pub fn write_string(value: &str) -> u64 {
+    let bytes = value.as_bytes();
+    let pointer = allocate(bytes.len());
+    if pointer.is_null() {
+        return pack_pointer(0, 0);
+    }
+    unsafe { pointer.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+    pack_pointer(pointer as usize as u32, bytes.len() as u32)
+}
+
+
+/// Runs the inference with the model and image paths and the JSON options and returns the JSON result.
+/// The inference function gets the model path, the image path and the number of repeats.
+pub fn run_inference_with_json<F>(model: &str, image: &str, options_json: &str, inference: F) -> String
+where F: Fn(&str, &str, u32) -> Result<(f32, i32), RunnerError> {
+    let result = match InferenceOptions::from_json(options_json) {
+        Ok(options) => InferenceResult::new(model, image, inference(model, image, options.repeats)),
+        Err(message) => InferenceResult::from_error(model, image, ErrorType::MissingImageName, message),
+    };
+    result.to_json()
+}
+
+/// Implements the exported inference function of the guest: reads the strings written by the host,
+/// runs the inference and writes the JSON result to the guest memory.
+///
+/// # Safety
+/// The pointers must point to strings of the given lengths written by the host.
+pub unsafe fn run_inference_from_memory<F>(
+    model: (*const u8, u32),
+    image: (*const u8, u32),
+    options: (*const u8, u32),
+    inference: F,
+) -> u64
+where F: Fn(&str, &str, u32) -> Result<(f32, i32), RunnerError> {
+    let strings = read_string(model.0, model.1 as usize)
+        .and_then(|model| read_string(image.0, image.1 as usize).map(|image| (model, image)))
+        .and_then(|(model, image)| read_string(options.0, options.1 as usize).map(|options| (model, image, options)));
+
+    let result_json = match strings {
+        Ok((model, image, options)) => run_inference_with_json(&model, &image, &options, inference),
+        Err(message) => InferenceResult::from_error("", "", ErrorType::MissingImageName, message).to_json(),
+    };
+    write_string(&result_json)
+}
@@ -1,95 +1,87 @@
+extern crate image;
 extern crate local_names;
 extern crate tract_onnx;
+#[cfg(test)]
+extern crate test_models;
 
-use std::time::Instant;
+use std::{env, process, time::Instant};
+use image::DynamicImage;
 use tract_onnx::{
     self as tonnx,
-    prelude::{self as tp, tvec, Framework, InferenceModelExt, Tensor},
+    prelude::{tvec, Framework, InferenceModelExt, Tensor},
     tract_core::ops::TypedOp,
     tract_hir::prelude::{SimplePlan, TypedFact, Graph}
 };
-use local_names::{get_image_name, get_model_name};
+use local_names::{get_error_code, get_exit_code, get_image_name, get_model_name, validate_output, verify_listing_hash, Arguments, ErrorContext, ErrorType, ModelMetadata, RunnerError};
+use local_names::{ground_truth_file, resize_override, ResizeMode, EVALUATE_OPTION, RESIZE_OPTION, TOP_COUNT};
+use local_names::evaluation;
+use local_names::string_abi;
 
 type RunnableModel = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
 
 
-#[derive(Debug)]
-pub enum ErrorType {
-    SessionCreation,
-    Optimization,
-    Threads,
-    ModelLoad,
-    ImageLoad,
-    ImageConversion,
-    ModelRun,
-    TensorExtract,
-    NoResult,
-    MissingImageName,
-}
-
 
-fn get_error_code(error: ErrorType) -> i32 {
-    match error {
-        ErrorType::SessionCreation => -1,
-        ErrorType::Optimization => -2,
-        ErrorType::Threads => -3,
-        ErrorType::ModelLoad => -4,
-        ErrorType::ImageLoad => -5,
-        ErrorType::ImageConversion => -6,
-        ErrorType::ModelRun => -7,
-        ErrorType::TensorExtract => -8,
-        ErrorType::NoResult => -9,
-        ErrorType::MissingImageName => -10,
-    }
+/// Resizes the image to the input size of the model according to its resize mode and creates the normalized
+/// input tensor data from it.
+fn preprocess_image(image: &DynamicImage, metadata: &ModelMetadata) -> Vec<f32> {
+    let geometry = metadata.resize_geometry(image.width(), image.height());
+    let resized = image::imageops::resize(
+        &image.to_rgb8(),
+        geometry.width,
+        geometry.height,
+        image::imageops::FilterType::Triangle
+    );
+    metadata.normalize_resized_pixels(&geometry, |x, y, c| resized[(x, y)][c])
 }
 
-
 // Adapted from:
 // https://github.com/sonos/tract/blob/fd690600d1993ca4b90e2e73db4c5dccbecf5ded/examples/onnx-mobilenet-v2/src/main.rs
 // and based further on:
 // https://github.com/LiquidAI-project/wasmiot-modules/blob/main/modules/wasi_mobilenet_inference_onnx/src/inference.rs
-fn get_result(runnable_model: &RunnableModel, image_name: String, verbose: bool) -> Result<(f32, i32), ErrorType> {
+fn get_result(
+    runnable_model: &RunnableModel,
+    metadata: &ModelMetadata,
+    image_name: String,
+    verbose: bool
+) -> Result<(f32, i32), RunnerError> {
+    get_top_results(runnable_model, metadata, image_name, 1, verbose)
+        .and_then(|results| results.first().cloned().ok_or_else(|| ErrorType::NoResult.into()))
+}
+
+/// Runs the inference with the image and returns the `count` best scores with their class indexes.
+fn get_top_results(
+    runnable_model: &RunnableModel,
+    metadata: &ModelMetadata,
+    image_name: String,
+    count: usize,
+    verbose: bool
+) -> Result<Vec<(f32, i32)>, RunnerError> {
     let result_start = Instant::now();
 
-    let image = match image::open(&image_name) {
-        Ok(image) => image.to_rgb8(),
-        Err(e) => {
-            println!("{:?}", e);
-            return Err(ErrorType::ImageLoad);
-        }
-    };
-    let resized =
-        image::imageops::resize(&image, 224, 224, image::imageops::FilterType::Triangle);
-    let image: Tensor = tp::tract_ndarray::Array4::from_shape_fn((1, 3, 224, 224),  |(_, c, y, x)| {
-        let mean = [0.485, 0.456, 0.406][c];
-        let std = [0.229, 0.224, 0.225][c];
-        (resized[(x as _, y as _)][c] as f32 / 255.0 - mean) / std
-    }).into();
+    let image = image::open(&image_name)
+        .error_context(ErrorType::ImageLoad, || format!("Could not load the image {}", image_name))?;
+    let input_data = preprocess_image(&image, metadata);
+    let image = Tensor::from_shape(&metadata.input_dimensions(), &input_data)
+        .error_context(ErrorType::ImageConversion, || "Could not create the input tensor")?;
 
     let image_load_time = result_start.elapsed();
 
-    let result = match runnable_model.run(tvec!(image.into())) {
-        Ok(result) => result,
-        Err(error) => {
-            eprintln!("{:?}", error);
-            return Err(ErrorType::ModelRun);
-        }
-    };
+    let result = runnable_model
+        .run(tvec!(image.into()))
+        .error_context(ErrorType::ModelRun, || "Could not run the model")?;
     let model_run_time = result_start.elapsed() - image_load_time;
 
-    let final_result = match result[0].to_array_view::<f32>() {
-        Ok(array_result) =>
-            match array_result.iter()
-                .cloned()
-                .zip(1..)
-                .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap()) {
-                    Some(label_result) => label_result,
-                    None => return Err(ErrorType::NoResult),
-                },
-        Err(_) => {
-            return Err(ErrorType::TensorExtract);
-        }
-    };
+    let scores: Vec<f32> = result[0]
+        .to_array_view::<f32>()
+        .error_context(ErrorType::TensorExtract, || "Could not read the output tensor as f32 values")?
+        .iter()
+        .cloned()
+        .collect();
+    validate_output(&scores, &metadata.input_dimensions(), || input_data)?;
+    let final_result = metadata.top_results(&scores, count, verbose);
+    if final_result.is_empty() {
+        return Err(ErrorType::NoResult.into());
+    }
     let result_calculation_time = result_start.elapsed() - model_run_time - image_load_time;
 
     if verbose {
@@ -104,6 +96,12 @@ fn get_result(runnable_model: &RunnableModel, image_name: String, verbose: bool)
 
 #[no_mangle]
 pub fn run_inference(model_index: i32, image_index: i32, repeats: u32) -> i32 {
+    // the indexes are only meaningful if the host sees the same models and images as the guest
+    if let Err(error) = verify_listing_hash() {
+        println!("Error: {}", error);
+        return get_error_code(ErrorType::ListingMismatch);
+    }
+
     let model_filename = match get_model_name(model_index) {
         Some(filename) => filename,
         None => {
@@ -120,53 +118,185 @@ pub fn run_inference(model_index: i32, image_index: i32, repeats: u32) -> i32 {
         }
     };
 
-    let start: Instant = Instant::now();
+    run_inference_for_files(&model_filename, &image_name, repeats)
+}
 
-    let model_input = match tonnx::onnx().model_for_path(&model_filename) {
-        Ok(model) => model,
-        Err(e) => {
-            eprintln!("{:?}", e);
-            return get_error_code(ErrorType::ModelLoad);
-        }
-    };
-    let model_optimized = match model_input.into_optimized() {
-        Ok(model) => model,
-        Err(_) => {
-            return get_error_code(ErrorType::Optimization);
-        }
-    };
-    let model_runnable: RunnableModel = match model_optimized.into_runnable() {
-        Ok(model) => model,
-        Err(_) => {
-            return get_error_code(ErrorType::SessionCreation);
+/// Runs the inference with the given model and image files and returns the class index or a negative error code.
+pub fn run_inference_for_files(model_filename: &str, image_name: &str, repeats: u32) -> i32 {
+    match get_inference_result(model_filename, image_name, repeats) {
+        Ok((score, class)) => {
+            println!("{}: {} (score: {})", image_name, class, score);
+            if let Ok(metadata) = ModelMetadata::for_model(model_filename) {
+                metadata.check_result(image_name, class);
+            }
+            class
+        },
+        Err(error) => {
+            println!("Error: {}", error.report());
+            error.code()
         }
-    };
+    }
+}
+
+/// Loads the model and optimizes it for running.
+fn load_model(model_filename: &str) -> Result<RunnableModel, RunnerError> {
+    tonnx::onnx()
+        .model_for_path(model_filename)
+        .error_context(ErrorType::ModelLoad, || format!("Could not load the model {}", model_filename))?
+        .into_optimized()
+        .error_context(ErrorType::Optimization, || "Could not optimize the model")?
+        .into_runnable()
+        .error_context(ErrorType::SessionCreation, || "Could not create a runnable plan of the model")
+}
+
+/// Loads the model and runs the inference with the given image `repeats` + 1 times and returns the first result.
+fn get_inference_result(model_filename: &str, image_name: &str, repeats: u32) -> Result<(f32, i32), RunnerError> {
+    let start: Instant = Instant::now();
+
+    let metadata = ModelMetadata::for_model(model_filename).map_err(|error| RunnerError::new(ErrorType::ModelLoad, error))?;
+
+    let model_runnable = load_model(model_filename)?;
     let model_load_time = start.elapsed();
     println!("Loading the model took {:?}", model_load_time);
 
-    let result = get_result(&model_runnable, image_name.clone(), true);
+    let result = get_result(&model_runnable, &metadata, image_name.to_string(), true);
     let result_calculation_time = start.elapsed() - model_load_time;
 
     for _ in 0..repeats {
-        let _ = get_result(&model_runnable, image_name.clone(), false);
+        let _ = get_result(&model_runnable, &metadata, image_name.to_string(), false);
     }
     let repeat_time = start.elapsed() - model_load_time - result_calculation_time;
     println!("\nRunning the model {} times took {:?}\n", repeats, repeat_time);
 
+    result
+}
+
+
+
+/// Allocates memory for the host to write the arguments of `run_inference_json`.
+#[no_mangle]
+pub extern "C" fn allocate(size: u32) -> *mut u8 {
+    string_abi::allocate(size as usize)
+}
+
+/// Frees memory allocated with `allocate` or returned by `run_inference_json`.
+///
+/// # Safety
+/// The pointer must have been allocated by this module with the given size.
+#[no_mangle]
+pub unsafe extern "C" fn deallocate(pointer: *mut u8, size: u32) {
+    string_abi::deallocate(pointer, size as usize)
+}
+
+/// Runs the inference with the model and image paths and JSON options written by the host to the guest memory.
+/// Returns the pointer and length of the JSON result packed into an u64.
+///
+/// # Safety
+/// The pointers must point to strings of the given lengths allocated with `allocate`.
+#[no_mangle]
+pub unsafe extern "C" fn run_inference_json(
+    model_pointer: *const u8,
+    model_length: u32,
+    image_pointer: *const u8,
+    image_length: u32,
+    options_pointer: *const u8,
+    options_length: u32,
+) -> u64 {
+    string_abi::run_inference_from_memory(
+        (model_pointer, model_length),
+        (image_pointer, image_length),
+        (options_pointer, options_length),
+        get_inference_result,
+    )
+}
+
+
+/// Runs the accuracy evaluation of the model over the images in the folder listed in the ground truth file.
+/// The resize mode of the model can be overridden for comparing the accuracy of the modes.
+/// Returns 0 when the evaluation could be run, otherwise a negative error code.
+fn run_evaluation(
+    model_filename: &str,
+    image_folder: &str,
+    ground_truth_file: &str,
+    resize_mode: Option<ResizeMode>,
+) -> i32 {
+    let mut metadata = match ModelMetadata::for_model(model_filename) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            println!("{}", error);
+            return get_error_code(ErrorType::ModelLoad);
+        }
+    };
+    if let Some(resize_mode) = resize_mode {
+        metadata.resize = resize_mode;
+    }
+    let model_runnable = match load_model(model_filename) {
+        Ok(model) => model,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            return error.code();
+        }
+    };
+
+    let result = evaluation::print_evaluation(model_filename, image_folder, ground_truth_file, |image_name| {
+        get_top_results(&model_runnable, &metadata, image_name.to_string(), TOP_COUNT, false)
+            .map(|results| results.into_iter().map(|(_, class)| class).collect())
+    });
     match result {
-        Ok((score, class)) => {
-            println!("{}: {} (score: {})", image_name, class, score);
-            class
-        },
+        Ok(()) => 0,
         Err(error) => {
-            println!("Error: {:?}", error);
-            get_error_code(error)
+            println!("Error: {}", error.report());
+            error.code()
         }
     }
 }
 
 
-
+/// Runs the inference as a WASI command with the model path, image path and number of repeats as arguments.
+/// With `--evaluate=<ground truth file>` the image path is a folder and the accuracy over its images is evaluated.
+/// With `--resize=<mode>` the resize mode of the model is overridden in the evaluation, it is an error without `--evaluate`.
 fn main() {
-    run_inference(1, 1, 10);
+    let args: Vec<String> = env::args().collect();
+    let arguments = match Arguments::parse(&args, &[EVALUATE_OPTION, RESIZE_OPTION]) {
+        Ok(arguments) => arguments,
+        Err(error) => {
+            println!("Error: {}\n", error);
+            println!("{}", Arguments::usage(&args[0], &[EVALUATE_OPTION, RESIZE_OPTION]));
+            process::exit(get_exit_code(get_error_code(ErrorType::MissingImageName)));
+        }
+    };
+    let resize_mode = match resize_override(&arguments) {
+        Ok(resize_mode) => resize_mode,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            process::exit(error.exit_code());
+        }
+    };
+
+    let ground_truth_file = match ground_truth_file(&arguments) {
+        Ok(ground_truth_file) => ground_truth_file,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            process::exit(error.exit_code());
+        }
+    };
+
+    let result = match ground_truth_file {
+        Some(ground_truth_file) => run_evaluation(
+            &arguments.model,
+            &arguments.image,
+            ground_truth_file,
+            resize_mode,
+        ),
+        None => run_inference_for_files(&arguments.model, &arguments.image, arguments.repeats),
+    };
+    process::exit(get_exit_code(result));
+}
+
+
+#[test]
+fn test_preprocess_image() {
+    test_models::preprocessing::check_preprocessing(|image, metadata| {
+        Ok::<Vec<f32>, RunnerError>(preprocess_image(image, metadata))
+    });
 }
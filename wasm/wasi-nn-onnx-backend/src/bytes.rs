@@ -0,0 +1,28 @@
+/// Converts a vector of f32 values to little-endian bytes.
+pub fn f32_vec_to_bytes(data: Vec<f32>) -> Vec<u8> {
+    data.into_iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+/// Converts little-endian bytes to a vector of f32 values.
+/// Trailing bytes that do not form a full f32 value are ignored.
+pub fn bytes_to_f32_vec(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+#[test]
+fn test_f32_bytes_array_and_back() {
+    let case = vec![0.0_f32, 1.1, 2.2, 3.3];
+    let bytes = f32_vec_to_bytes(case.clone());
+    let res = bytes_to_f32_vec(&bytes);
+    assert_eq!(case, res);
+}
+
+#[test]
+fn test_bytes_array_to_f32_array() {
+    let bytes = vec![0x00, 0x00, 0x48, 0x41, 0x00, 0x00, 0x48, 0x41];
+    let res = bytes_to_f32_vec(&bytes);
+    assert!((12.5 - res[0]).abs() < f32::EPSILON);
+    assert!((12.5 - res[1]).abs() < f32::EPSILON);
+}
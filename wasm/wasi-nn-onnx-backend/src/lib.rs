@@ -0,0 +1,169 @@
+//! Implements a `wasi-nn` backend for `wasmtime-wasi-nn` using ONNX via ort.
+//!
+//! Based on the `onnxruntime` backend in the wasmtime repository
+//! (crates/wasi-nn/src/backend/onnxruntime.rs) but written against the ort version
+//! pinned in this repository, so that the wasmtime submodule does not need to be modified.
+
+extern crate anyhow;
+extern crate ort;
+extern crate wasmtime_wasi_nn;
+
+pub mod bytes;
+
+use anyhow::anyhow;
+use bytes::{bytes_to_f32_vec, f32_vec_to_bytes};
+use ort::{inputs, GraphOptimizationLevel, Session, SessionInputValue};
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use wasmtime_wasi_nn::{
+    backend::{BackendError, BackendExecutionContext, BackendFromDir, BackendGraph, BackendInner},
+    wit::types::{ExecutionTarget, GraphEncoding, Tensor, TensorType},
+    ExecutionContext, Graph,
+};
+
+/// The filename used for the model when loading a graph from a directory.
+const MODEL_FILENAME: &str = "model.onnx";
+
+
+/// ONNX backend that can be given to `wasmtime_wasi_nn::WasiNnCtx`.
+#[derive(Default)]
+pub struct OnnxBackend();
+unsafe impl Send for OnnxBackend {}
+unsafe impl Sync for OnnxBackend {}
+
+impl BackendInner for OnnxBackend {
+    fn encoding(&self) -> GraphEncoding {
+        GraphEncoding::Onnx
+    }
+
+    fn load(&mut self, builders: &[&[u8]], target: ExecutionTarget) -> Result<Graph, BackendError> {
+        if builders.len() != 1 {
+            return Err(BackendError::InvalidNumberOfBuilders(1, builders.len()));
+        }
+
+        let session = Session::builder()
+            .map_err(backend_error)?
+            .with_optimization_level(GraphOptimizationLevel::Level3)
+            .map_err(backend_error)?
+            .commit_from_memory(builders[0])
+            .map_err(backend_error)?;
+
+        let graph: Box<dyn BackendGraph> = Box::new(OnnxGraph(Arc::new(Mutex::new(session)), target));
+        Ok(graph.into())
+    }
+
+    fn as_dir_loadable(&mut self) -> Option<&mut dyn BackendFromDir> {
+        Some(self)
+    }
+}
+
+impl BackendFromDir for OnnxBackend {
+    fn load_from_dir(&mut self, path: &Path, target: ExecutionTarget) -> Result<Graph, BackendError> {
+        let model = fs::read(path.join(MODEL_FILENAME)).map_err(backend_error)?;
+        self.load(&[&model], target)
+    }
+}
+
+
+/// A loaded ONNX model.
+struct OnnxGraph(Arc<Mutex<Session>>, #[allow(dead_code)] ExecutionTarget);
+unsafe impl Send for OnnxGraph {}
+unsafe impl Sync for OnnxGraph {}
+
+impl BackendGraph for OnnxGraph {
+    fn init_execution_context(&self) -> Result<ExecutionContext, BackendError> {
+        let session = self.0.lock().map_err(|_| anyhow!("the ONNX session lock is poisoned"))?;
+        let inputs = session.inputs.iter().map(|_| None).collect::<Vec<_>>();
+        let outputs = session.outputs.iter().map(|_| None).collect::<Vec<_>>();
+
+        let context: Box<dyn BackendExecutionContext> = Box::new(OnnxExecutionContext {
+            session: self.0.clone(),
+            inputs,
+            outputs,
+        });
+        Ok(context.into())
+    }
+}
+
+
+/// Execution context holding the input tensors and the computed outputs for one ONNX session.
+struct OnnxExecutionContext {
+    session: Arc<Mutex<Session>>,
+    inputs: Vec<Option<Tensor>>,
+    outputs: Vec<Option<Vec<u8>>>,
+}
+unsafe impl Send for OnnxExecutionContext {}
+unsafe impl Sync for OnnxExecutionContext {}
+
+impl BackendExecutionContext for OnnxExecutionContext {
+    fn set_input(&mut self, index: u32, tensor: &Tensor) -> Result<(), BackendError> {
+        let input_count = self.inputs.len();
+        match self.inputs.get_mut(index as usize) {
+            Some(input) => {
+                input.replace(tensor.clone());
+                Ok(())
+            },
+            None => Err(anyhow!("invalid input index {} (the model has {} inputs)", index, input_count).into()),
+        }
+    }
+
+    fn compute(&mut self) -> Result<(), BackendError> {
+        let mut session_inputs: Vec<SessionInputValue<'_>> = Vec::with_capacity(self.inputs.len());
+        for (index, input) in self.inputs.iter().enumerate() {
+            let input = input
+                .as_ref()
+                .ok_or_else(|| anyhow!("input {} has not been set", index))?;
+            session_inputs.extend(to_input_value(input)?);
+        }
+
+        let session = self.session.lock().map_err(|_| anyhow!("the ONNX session lock is poisoned"))?;
+        let session_outputs = session.run(session_inputs.as_slice()).map_err(backend_error)?;
+
+        for (index, output) in self.outputs.iter_mut().enumerate() {
+            // only f32 outputs are supported for now
+            let (_, data): (Vec<i64>, &[f32]) = session_outputs[index]
+                .try_extract_raw_tensor()
+                .map_err(backend_error)?;
+            output.replace(f32_vec_to_bytes(data.to_vec()));
+        }
+        Ok(())
+    }
+
+    fn get_output(&mut self, index: u32, destination: &mut [u8]) -> Result<u32, BackendError> {
+        let output = match self.outputs.get(index as usize) {
+            Some(Some(output)) => output,
+            Some(None) => return Err(anyhow!("output {} is missing, has compute been called?", index).into()),
+            None => return Err(anyhow!("invalid output index {} (the model has {} outputs)", index, self.outputs.len()).into()),
+        };
+        if output.len() > destination.len() {
+            return Err(BackendError::NotEnoughMemory(output.len()));
+        }
+
+        destination[..output.len()].copy_from_slice(output);
+        Ok(output.len() as u32)
+    }
+}
+
+
+/// Converts a wasi-nn tensor to an ONNX session input.
+fn to_input_value(tensor: &Tensor) -> Result<[SessionInputValue<'static>; 1], BackendError> {
+    let dimensions = tensor.dimensions
+        .iter()
+        .map(|dimension| *dimension as i64)
+        .collect::<Vec<_>>();
+
+    match tensor.tensor_type {
+        TensorType::Fp32 => {
+            let data = bytes_to_f32_vec(&tensor.data);
+            inputs![(dimensions, Arc::new(data.into_boxed_slice()))].map_err(backend_error)
+        },
+        other => Err(anyhow!("input tensor type {:?} is not supported by the ONNX backend", other).into()),
+    }
+}
+
+fn backend_error(error: impl Into<anyhow::Error>) -> BackendError {
+    BackendError::BackendAccess(error.into())
+}
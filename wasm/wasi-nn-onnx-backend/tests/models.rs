@@ -0,0 +1,39 @@
+// Runs the synthetic test models from the test-models crate through the backend, so no downloaded models are needed.
+
+extern crate test_models;
+extern crate wasi_nn_onnx_backend;
+extern crate wasmtime_wasi_nn;
+
+use test_models::{check_models, to_bytes, ModelCase};
+use wasi_nn_onnx_backend::{
+    bytes::{bytes_to_f32_vec, f32_vec_to_bytes},
+    OnnxBackend,
+};
+use wasmtime_wasi_nn::{
+    backend::{BackendError, BackendInner},
+    wit::types::{ExecutionTarget, Tensor, TensorType},
+};
+
+
+fn run_model(case: &ModelCase) -> Result<Vec<f32>, BackendError> {
+    let mut backend = OnnxBackend::default();
+    let graph = backend.load(&[&to_bytes(&case.model)], ExecutionTarget::Cpu)?;
+    let mut context = graph.init_execution_context()?;
+
+    let tensor = Tensor {
+        dimensions: case.input_shape.iter().map(|dimension| *dimension as u32).collect(),
+        tensor_type: TensorType::Fp32,
+        data: f32_vec_to_bytes(case.input.clone()),
+    };
+    context.set_input(0, &tensor)?;
+    context.compute()?;
+
+    let mut output = vec![0u8; case.expected.len() * std::mem::size_of::<f32>()];
+    let output_bytes = context.get_output(0, &mut output)? as usize;
+    Ok(bytes_to_f32_vec(&output[..output_bytes]))
+}
+
+#[test]
+fn test_models() {
+    check_models(run_model);
+}
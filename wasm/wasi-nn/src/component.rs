@@ -0,0 +1,101 @@
+// The wasi-nn functionality through the WIT interface when the program is built as a component.
+
+use local_names::string_abi;
+use std::{fmt, fs, marker::PhantomData};
+
+wit_bindgen::generate!({
+    world: "inference-test",
+    path: "wit",
+    generate_all,
+});
+
+use self::wasi::nn::{
+    errors::Error as NnError,
+    graph::{self, ExecutionTarget, Graph, GraphEncoding},
+    inference::{self, GraphExecutionContext},
+    tensor::{Tensor, TensorType},
+};
+
+
+#[derive(Debug)]
+pub enum Error {
+    ModelRead(String),
+    WasiNn(NnError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ModelRead(message) => write!(f, "could not read the model file: {}", message),
+            Error::WasiNn(error) => write!(f, "wasi-nn error: {:?}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<NnError> for Error {
+    fn from(error: NnError) -> Self {
+        Error::WasiNn(error)
+    }
+}
+
+
+pub struct Model(Graph);
+
+pub struct Context<'model>(GraphExecutionContext, PhantomData<&'model Model>);
+
+
+pub fn load_model(filename: &str) -> Result<Model, Error> {
+    let model_bytes = fs::read(filename).map_err(|error| Error::ModelRead(error.to_string()))?;
+    let graph = graph::load(&[model_bytes], GraphEncoding::Onnx, ExecutionTarget::Cpu)?;
+    Ok(Model(graph))
+}
+
+impl Model {
+    pub fn init_execution_context(&self) -> Result<Context<'_>, Error> {
+        let context = inference::init_execution_context(self.0)?;
+        Ok(Context(context, PhantomData))
+    }
+}
+
+impl<'model> Context<'model> {
+    pub fn set_input(&mut self, dimensions: &[usize], data: &[u8]) -> Result<(), Error> {
+        let tensor = Tensor {
+            dimensions: dimensions.iter().map(|dimension| *dimension as u32).collect(),
+            tensor_type: TensorType::Fp32,
+            data: data.to_vec(),
+        };
+        inference::set_input(self.0, 0, &tensor)?;
+        Ok(())
+    }
+
+    pub fn compute(&mut self) -> Result<(), Error> {
+        inference::compute(self.0)?;
+        Ok(())
+    }
+
+    /// Copies the first output tensor to the buffer and returns its size in bytes.
+    pub fn get_output(&mut self, buffer: &mut [f32]) -> Result<usize, Error> {
+        let output = inference::get_output(self.0, 0)?;
+        for (value, bytes) in buffer.iter_mut().zip(output.chunks_exact(4)) {
+            *value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Ok(output.len())
+    }
+}
+
+
+struct InferenceTest;
+
+impl Guest for InferenceTest {
+    fn run_inference(model_index: i32, image_index: i32, repeats: u32) -> i32 {
+        ::run_inference(model_index, image_index, repeats)
+    }
+
+    fn run_inference_json(model: String, image: String, options: String) -> String {
+        string_abi::run_inference_with_json(&model, &image, &options, ::get_inference_result)
+    }
+}
+
+export!(InferenceTest);
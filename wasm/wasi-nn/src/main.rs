@@ -1,40 +1,29 @@
 extern crate image;
 // extern crate image2tensor;
 extern crate wasi_nn;
-extern crate anyhow;
-extern crate ndarray;
 extern crate local_names;
-
-use anyhow::Error;
-use std::{cmp::Ordering, ops::RangeFrom, time::Instant};
-use image::{imageops::FilterType, Pixel};
-use ndarray::s;
+#[cfg(feature = "component")]
+extern crate wit_bindgen;
+#[cfg(test)]
+extern crate test_models;
+
+#[cfg(feature = "component")]
+mod component;
+#[cfg(not(feature = "component"))]
+mod witx;
+mod preprocessing;
+
+use std::{env, process, time::Instant};
 // use image2tensor::{ColorOrder, TensorType};
-use wasi_nn::{ExecutionTarget, Graph, GraphBuilder, GraphEncoding, GraphExecutionContext};
-use local_names::{get_image_name, get_model_name};
-
-
-#[derive(Debug)]
-pub enum ErrorType {
-    SessionCreation,
-    Optimization,
-    Threads,
-    ModelLoad,
-    ImageLoad,
-    ImageConversion,
-    ModelRun,
-    TensorExtract,
-    NoResult,
-    MissingImageName,
-}
-
-
-fn load_model(filename: String) -> Result<Graph, wasi_nn::Error> {
-    GraphBuilder::new(
-        GraphEncoding::Onnx,
-        ExecutionTarget::CPU
-    ).build_from_files([filename])
-}
+use local_names::{get_error_code, get_exit_code, get_image_name, get_model_name, validate_output, verify_listing_hash, Arguments, ErrorContext, ErrorType, ModelMetadata, RunnerError};
+use local_names::{ground_truth_file, resize_override, ResizeMode, EVALUATE_OPTION, RESIZE_OPTION, TOP_COUNT};
+use local_names::evaluation;
+#[cfg(not(feature = "component"))]
+use local_names::string_abi;
+#[cfg(feature = "component")]
+use component as nn;
+#[cfg(not(feature = "component"))]
+use witx as nn;
 
 
 fn f32_vec_to_bytes(data: Vec<f32>) -> Vec<u8> {
@@ -51,101 +40,87 @@ fn f32_vec_to_bytes(data: Vec<f32>) -> Vec<u8> {
     result
 }
 
-pub fn image_to_tensor(
-    path: &str,
-    height: u32,
-    width: u32,
-) -> Result<Vec<u8>, Error> {
-    let image = image::imageops::resize(
-        &image::open(path)?,
-        width,
-        height,
-        ::FilterType::Triangle,
-    );
-
-    let mut array = ndarray::Array::from_shape_fn((1, 3, 224, 224), |(_, c, j, i)| {
-        let pixel = image.get_pixel(i as u32, j as u32);
-        let channels = pixel.channels();
-
-        // range [0, 255] -> range [0, 1]
-        (channels[c] as f32) / 255.0
-    });
-
-    // Normalize channels to mean=[0.485, 0.456, 0.406] and std=[0.229, 0.224, 0.225]
-    let mean = [0.485, 0.456, 0.406];
-    let std = [0.229, 0.224, 0.225];
-    for c in 0..3 {
-        let mut channel_array = array.slice_mut(s![0, c, .., ..]);
-        channel_array -= mean[c];
-        channel_array /= std[c];
-    }
+/// Converts the tensor bytes back to the values, for example for writing the input tensor to a file.
+fn bytes_to_f32_vec(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+}
 
-    Ok(f32_vec_to_bytes(array.as_slice().unwrap().to_vec()))
+pub fn image_to_tensor(path: &str, metadata: &ModelMetadata) -> Result<Vec<u8>, RunnerError> {
+    let image = preprocessing::open_image(path)?;
+    preprocessing::preprocess_image(&image, metadata).map(f32_vec_to_bytes)
 }
 
 
-fn load_image(path: String, width: u32, height: u32) -> Result<Vec<u8>, String> {
+fn load_image(path: String, metadata: &ModelMetadata) -> Result<Vec<u8>, RunnerError> {
 // fn load_image(path: &str, width: u32, height: u32, precision: TensorType, color_order: ColorOrder) -> Result<Vec<u8>, String> {
     // image2tensor::convert_image_to_tensor_bytes(path, width, height, precision, color_order)
-    image_to_tensor(&path, width, height).map_err(|e| e.to_string())
+    image_to_tensor(&path, metadata)
 }
 
 
-fn get_execution_context(model: &Graph) -> Result<GraphExecutionContext<'_>, ErrorType> {
-    match model.init_execution_context() {
-        Ok(context) => Ok(context),
-        Err(_) => Err(ErrorType::SessionCreation),
-    }
+fn load_model(model_filename: &str) -> Result<nn::Model, RunnerError> {
+    nn::load_model(model_filename)
+        .error_context(ErrorType::ModelLoad, || format!("Could not load the model {}", model_filename))
+}
+
+fn get_execution_context(model: &nn::Model) -> Result<nn::Context<'_>, RunnerError> {
+    model
+        .init_execution_context()
+        .error_context(ErrorType::SessionCreation, || "Could not create the execution context")
 }
 
 
 fn get_result(
     // model: &Graph,
-    context: &mut GraphExecutionContext<'_>,
+    context: &mut nn::Context<'_>,
+    metadata: &ModelMetadata,
+    image_name: String,
+    verbose: bool
+) -> Result<(f32, i32), RunnerError> {
+    get_top_results(context, metadata, image_name, 1, verbose)
+        .and_then(|results| results.first().cloned().ok_or_else(|| ErrorType::NoResult.into()))
+}
+
+/// Runs the inference with the image and returns the `count` best scores with their class indexes.
+fn get_top_results(
+    context: &mut nn::Context<'_>,
+    metadata: &ModelMetadata,
     image_name: String,
+    count: usize,
     verbose: bool
-) -> Result<(f32, i32), ErrorType> {
-    const MODEL_IMAGE_WIDTH: u32 = 224;
-    const MODEL_IMAGE_HEIGHT: u32 = 224;
+) -> Result<Vec<(f32, i32)>, RunnerError> {
     // const MODEL_IMAGE_PRECISION: TensorType = TensorType::F32;
     // const MODEL_IMAGE_COLOR_ORDER: ColorOrder = ColorOrder::RGB;
-    const MODEL_INPUT_DIMENSIONS: [usize; 4] = [1, 3, MODEL_IMAGE_WIDTH as usize, MODEL_IMAGE_HEIGHT as usize];
 
     let result_start: Instant = Instant::now();
 
-    let image = match load_image(image_name, MODEL_IMAGE_WIDTH, MODEL_IMAGE_HEIGHT) {
+    // the failures are ImageLoad when the image cannot be read and ImageConversion when it cannot be converted
+    let image = load_image(image_name, metadata)?;
     // let image = match load_image(image_name, MODEL_IMAGE_WIDTH, MODEL_IMAGE_HEIGHT, MODEL_IMAGE_PRECISION, MODEL_IMAGE_COLOR_ORDER) {
-        Ok(image) => image,
-        Err(_) => return Err(ErrorType::ImageLoad),
-    };
     let image_load_time = result_start.elapsed();
 
-    match context.set_input(0, wasi_nn::TensorType::F32, &MODEL_INPUT_DIMENSIONS, &image) {
-        Ok(_) => (),
-        Err(_) => return Err(ErrorType::ModelRun),
-    }
+    context
+        .set_input(&metadata.input_dimensions(), &image)
+        .error_context(ErrorType::ModelRun, || "Could not set the input tensor")?;
     let input_set_time = result_start.elapsed() - image_load_time;
 
-    match context.compute() {
-        Ok(_) => (),
-        Err(_) => return Err(ErrorType::ModelRun),
-    }
+    context.compute().error_context(ErrorType::ModelRun, || "Could not run the model")?;
     let model_run_time = result_start.elapsed() - input_set_time - image_load_time;
 
     const OUTPUT_BUFFER_CAPACITY: usize = 4000;  // arbitrary max size
     let mut output_buffer: Vec<f32> = vec![0.0; OUTPUT_BUFFER_CAPACITY];
-    match context.get_output(0, &mut output_buffer) {
-        Ok(_) => (),
-        Err(_) => return Err(ErrorType::TensorExtract),
-    }
+    let output_size = context
+        .get_output(&mut output_buffer)
+        .error_context(ErrorType::TensorExtract, || "Could not read the output tensor")?;
+    // only the values written by the backend are results, the rest of the buffer is unused
+    output_buffer.truncate(output_size / std::mem::size_of::<f32>());
     let tensor_extract_time = result_start.elapsed() - model_run_time - input_set_time - image_load_time;
 
-    let result = output_buffer
-        .iter()
-        .cloned()
-        .zip(RangeFrom::<i32>{start: 1})  // add the indexes for the labels
-        .max_by(|(score1, _), (score2, _)| score1.partial_cmp(score2).unwrap_or(Ordering::Equal))
-        .map_or_else(|| Err(ErrorType::NoResult), Ok);
+    validate_output(&output_buffer, &metadata.input_dimensions(), || bytes_to_f32_vec(&image))?;
+    let result = match metadata.top_results(&output_buffer, count, verbose) {
+        results if results.is_empty() => Err(ErrorType::NoResult.into()),
+        results => Ok(results),
+    };
     let result_calculation_time = result_start.elapsed() - tensor_extract_time - model_run_time - input_set_time - image_load_time;
 
     if verbose {
@@ -158,23 +133,14 @@ fn get_result(
 }
 
 
-fn get_error_code(error: ErrorType) -> i32 {
-    match error {
-        ErrorType::SessionCreation => -1,
-        ErrorType::Optimization => -2,
-        ErrorType::Threads => -3,
-        ErrorType::ModelLoad => -4,
-        ErrorType::ImageLoad => -5,
-        ErrorType::ImageConversion => -6,
-        ErrorType::ModelRun => -7,
-        ErrorType::TensorExtract => -8,
-        ErrorType::NoResult => -9,
-        ErrorType::MissingImageName => -10,
+#[cfg_attr(not(feature = "component"), no_mangle)]
+pub fn run_inference(model_index: i32, image_index: i32, repeats: u32) -> i32 {
+    // the indexes are only meaningful if the host sees the same models and images as the guest
+    if let Err(error) = verify_listing_hash() {
+        println!("Error: {}", error);
+        return get_error_code(ErrorType::ListingMismatch);
     }
-}
 
-#[no_mangle]
-pub fn run_inference(model_index: i32, image_index: i32, repeats: u32) -> i32 {
     let model_filename = match get_model_name(model_index) {
         Some(filename) => filename,
         None => {
@@ -191,51 +157,180 @@ pub fn run_inference(model_index: i32, image_index: i32, repeats: u32) -> i32 {
         }
     };
 
-    // println!("Running inference with model: {} and image: {}", model_filename, image_name);
-    let start: Instant = Instant::now();
+    run_inference_for_files(&model_filename, &image_name, repeats)
+}
 
-    let model = match load_model(model_filename) {
-        Ok(graph) => graph,
+/// Runs the inference with the given model and image files and returns the class index or a negative error code.
+pub fn run_inference_for_files(model_filename: &str, image_name: &str, repeats: u32) -> i32 {
+    match get_inference_result(model_filename, image_name, repeats) {
+        Ok((score, class)) => {
+            println!("{}: {} (score: {})", image_name, class, score);
+            if let Ok(metadata) = ModelMetadata::for_model(model_filename) {
+                metadata.check_result(image_name, class);
+            }
+            class
+        },
         Err(error) => {
-            println!("Error loading model: {:?}", error);
-            return get_error_code(ErrorType::ModelLoad);
+            println!("Error: {}", error.report());
+            error.code()
         }
-    };
+    }
+}
+
+/// Loads the model and runs the inference with the given image `repeats` + 1 times and returns the first result.
+fn get_inference_result(model_filename: &str, image_name: &str, repeats: u32) -> Result<(f32, i32), RunnerError> {
+    // println!("Running inference with model: {} and image: {}", model_filename, image_name);
+    let start: Instant = Instant::now();
+
+    let metadata = ModelMetadata::for_model(model_filename).map_err(|error| RunnerError::new(ErrorType::ModelLoad, error))?;
+
+    let model = load_model(model_filename)?;
     let model_load_time = start.elapsed();
     println!("Loading the model took {:?}", model_load_time);
 
-    let mut context = match get_execution_context(&model) {
-        Ok(context) => context,
-        Err(error) => {
-            println!("Error creating context: {:?}", error);
-            return get_error_code(error);
-        }
-    };
+    let mut context = get_execution_context(&model)?;
     let context_creation_time = start.elapsed() - model_load_time;
     println!("Execution context creation took {:?}", context_creation_time);
 
-    let result = get_result(&mut context, image_name.clone(), true);
+    let result = get_result(&mut context, &metadata, image_name.to_string(), true);
     let result_calculation_time = start.elapsed() - model_load_time - context_creation_time;
 
     for _ in 0..repeats {
-        let _ = get_result(&mut context, image_name.clone(), false);
+        let _ = get_result(&mut context, &metadata, image_name.to_string(), false);
     }
     let repeat_time = start.elapsed() - model_load_time - context_creation_time - result_calculation_time;
     println!("\nRunning the model {} times took {:?}\n", repeats, repeat_time);
 
+    result
+}
+
+
+/// Allocates memory for the host to write the arguments of `run_inference_json`.
+#[cfg(not(feature = "component"))]
+#[no_mangle]
+pub extern "C" fn allocate(size: u32) -> *mut u8 {
+    string_abi::allocate(size as usize)
+}
+
+/// Frees memory allocated with `allocate` or returned by `run_inference_json`.
+///
+/// # Safety
+/// The pointer must have been allocated by this module with the given size.
+#[cfg(not(feature = "component"))]
+#[no_mangle]
+pub unsafe extern "C" fn deallocate(pointer: *mut u8, size: u32) {
+    string_abi::deallocate(pointer, size as usize)
+}
+
+/// Runs the inference with the model and image paths and JSON options written by the host to the guest memory.
+/// Returns the pointer and length of the JSON result packed into an u64.
+///
+/// # Safety
+/// The pointers must point to strings of the given lengths allocated with `allocate`.
+#[cfg(not(feature = "component"))]
+#[no_mangle]
+pub unsafe extern "C" fn run_inference_json(
+    model_pointer: *const u8,
+    model_length: u32,
+    image_pointer: *const u8,
+    image_length: u32,
+    options_pointer: *const u8,
+    options_length: u32,
+) -> u64 {
+    string_abi::run_inference_from_memory(
+        (model_pointer, model_length),
+        (image_pointer, image_length),
+        (options_pointer, options_length),
+        get_inference_result,
+    )
+}
+
+
+/// Runs the accuracy evaluation of the model over the images in the folder listed in the ground truth file.
+/// The resize mode of the model can be overridden for comparing the accuracy of the modes.
+/// Returns 0 when the evaluation could be run, otherwise a negative error code.
+fn run_evaluation(
+    model_filename: &str,
+    image_folder: &str,
+    ground_truth_file: &str,
+    resize_mode: Option<ResizeMode>,
+) -> i32 {
+    let mut metadata = match ModelMetadata::for_model(model_filename) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            println!("Error loading model metadata: {}", error);
+            return get_error_code(ErrorType::ModelLoad);
+        }
+    };
+    if let Some(resize_mode) = resize_mode {
+        metadata.resize = resize_mode;
+    }
+    let model = match load_model(model_filename) {
+        Ok(graph) => graph,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            return error.code();
+        }
+    };
+    let mut context = match get_execution_context(&model) {
+        Ok(context) => context,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            return error.code();
+        }
+    };
+
+    let result = evaluation::print_evaluation(model_filename, image_folder, ground_truth_file, |image_name| {
+        get_top_results(&mut context, &metadata, image_name.to_string(), TOP_COUNT, false)
+            .map(|results| results.into_iter().map(|(_, class)| class).collect())
+    });
     match result {
-        Ok((score, class)) => {
-            println!("{}: {} (score: {})", image_name, class, score);
-            class
-        },
+        Ok(()) => 0,
         Err(error) => {
-            println!("Error: {:?}", error);
-            get_error_code(error)
+            println!("Error: {}", error.report());
+            error.code()
         }
     }
 }
 
 
+/// Runs the inference as a WASI command with the model path, image path and number of repeats as arguments.
+/// With `--evaluate=<ground truth file>` the image path is a folder and the accuracy over its images is evaluated.
+/// With `--resize=<mode>` the resize mode of the model is overridden in the evaluation, it is an error without `--evaluate`.
 fn main() {
-    run_inference(1, 1, 10);
+    let args: Vec<String> = env::args().collect();
+    let arguments = match Arguments::parse(&args, &[EVALUATE_OPTION, RESIZE_OPTION]) {
+        Ok(arguments) => arguments,
+        Err(error) => {
+            println!("Error: {}\n", error);
+            println!("{}", Arguments::usage(&args[0], &[EVALUATE_OPTION, RESIZE_OPTION]));
+            process::exit(get_exit_code(get_error_code(ErrorType::MissingImageName)));
+        }
+    };
+    let resize_mode = match resize_override(&arguments) {
+        Ok(resize_mode) => resize_mode,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            process::exit(error.exit_code());
+        }
+    };
+
+    let ground_truth_file = match ground_truth_file(&arguments) {
+        Ok(ground_truth_file) => ground_truth_file,
+        Err(error) => {
+            println!("Error: {}", error.report());
+            process::exit(error.exit_code());
+        }
+    };
+
+    let result = match ground_truth_file {
+        Some(ground_truth_file) => run_evaluation(
+            &arguments.model,
+            &arguments.image,
+            ground_truth_file,
+            resize_mode,
+        ),
+        None => run_inference_for_files(&arguments.model, &arguments.image, arguments.repeats),
+    };
+    process::exit(get_exit_code(result));
 }
@@ -0,0 +1,167 @@
+// The image preprocessing of the guest: the image is decoded with its EXIF orientation applied, converted from
+// any color type (grayscale, with alpha, 16-bit or floating point) to 8-bit RGB with the transparent pixels
+// composited onto the background color of the model, resized to the input size according to the resize mode of
+// the model and normalized.
+
+use image::{imageops::FilterType, metadata::Orientation, DynamicImage, ImageDecoder, ImageReader, Rgb, RgbImage};
+use local_names::{ErrorContext, ErrorType, ModelMetadata, RunnerError};
+
+
+/// Reads the image file and rotates or flips it according to its EXIF orientation.
+pub fn open_image(path: &str) -> Result<DynamicImage, RunnerError> {
+    let message = || format!("Could not load the image {}", path);
+
+    let mut decoder = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .error_context(ErrorType::ImageLoad, message)?
+        .into_decoder()
+        .error_context(ErrorType::ImageLoad, message)?;
+    let orientation = decoder.orientation().unwrap_or_else(|error| {
+        println!("Could not read the orientation of the image {}, using it as is: {}", path, error);
+        Orientation::NoTransforms
+    });
+
+    let mut image = DynamicImage::from_decoder(decoder).error_context(ErrorType::ImageLoad, message)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Converts the image to 8-bit RGB. The pixels of images with an alpha channel are composited onto the background.
+pub fn to_rgb(image: &DynamicImage, background: [u8; 3]) -> Result<RgbImage, RunnerError> {
+    if image.width() == 0 || image.height() == 0 {
+        return Err(RunnerError::new(ErrorType::ImageConversion, "Could not convert the image: the image is empty"));
+    }
+    if !image.color().has_alpha() {
+        return Ok(image.to_rgb8());
+    }
+
+    let rgba = image.to_rgba32f();
+    let background = background.map(|value| value as f32 / 255.0);
+    Ok(RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = rgba.get_pixel(x, y);
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        Rgb([0, 1, 2].map(|channel| {
+            let value = pixel[channel] * alpha + background[channel] * (1.0 - alpha);
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        }))
+    }))
+}
+
+/// Converts the image to RGB, resizes it to the input size of the model according to its resize mode
+/// and creates the normalized input tensor data.
+pub fn preprocess_image(image: &DynamicImage, metadata: &ModelMetadata) -> Result<Vec<f32>, RunnerError> {
+    let rgb = to_rgb(image, metadata.background)?;
+    let geometry = metadata.resize_geometry(rgb.width(), rgb.height());
+    let image = image::imageops::resize(&rgb, geometry.width, geometry.height, FilterType::Triangle);
+
+    // range [0, 255] -> normalized with the mean and std of the model, in the input layout of the model
+    Ok(metadata.normalize_resized_pixels(&geometry, |x, y, c| image[(x, y)][c]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, LumaA, Rgba, RgbaImage};
+    use std::{env, fs, io::Cursor};
+
+    const BACKGROUND: [u8; 3] = [10, 20, 30];
+
+    #[test]
+    fn test_reference_tensors() {
+        ::test_models::preprocessing::check_preprocessing(preprocess_image);
+    }
+
+    #[test]
+    fn test_alpha_compositing() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(3, 1, |x, _| match x {
+            0 => Rgba([200, 100, 50, 255]),
+            1 => Rgba([200, 100, 50, 0]),
+            _ => Rgba([210, 120, 30, 128]),
+        }));
+        let rgb = to_rgb(&image, BACKGROUND).unwrap();
+        assert_eq!(rgb[(0, 0)], Rgb([200, 100, 50]));
+        assert_eq!(rgb[(1, 0)], Rgb(BACKGROUND));
+        // 128 / 255 of the color and the rest of the background
+        assert_eq!(rgb[(2, 0)], Rgb([110, 70, 30]));
+    }
+
+    #[test]
+    fn test_16_bit_grayscale_alpha() {
+        let image = DynamicImage::ImageLumaA16(image::ImageBuffer::from_pixel(2, 2, LumaA([65535u16, 0])));
+        assert_eq!(to_rgb(&image, BACKGROUND).unwrap()[(1, 1)], Rgb(BACKGROUND));
+        let image = DynamicImage::ImageLumaA16(image::ImageBuffer::from_pixel(2, 2, LumaA([257u16 * 40, 65535])));
+        assert_eq!(to_rgb(&image, BACKGROUND).unwrap()[(1, 1)], Rgb([40, 40, 40]));
+    }
+
+    #[test]
+    fn test_empty_image() {
+        let image = DynamicImage::new_rgb8(0, 0);
+        let metadata = ModelMetadata::default();
+        assert_eq!(preprocess_image(&image, &metadata).unwrap_err().kind(), ErrorType::ImageConversion);
+    }
+
+    #[test]
+    fn test_missing_image() {
+        let error = open_image("no-such-image.png").unwrap_err();
+        assert_eq!(error.kind(), ErrorType::ImageLoad);
+        // the error from the file system is kept as the cause
+        assert!(error.report().contains("caused by: "), "{}", error.report());
+    }
+
+    /// The CRC-32 checksum of a PNG chunk.
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = 0xffff_ffffu32;
+        for byte in data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = match crc & 1 {
+                    1 => (crc >> 1) ^ 0xedb8_8320,
+                    _ => crc >> 1,
+                };
+            }
+        }
+        !crc
+    }
+
+    /// A PNG image with an `eXIf` chunk that gives the EXIF orientation after the header chunk.
+    fn png_with_orientation(image: &DynamicImage, orientation: u16) -> Vec<u8> {
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+
+        // big-endian TIFF header and a single IFD entry: the orientation tag (0x0112) as a SHORT value
+        let mut exif = vec![b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1];
+        exif.extend_from_slice(&orientation.to_be_bytes());
+        exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let mut chunk = (exif.len() as u32).to_be_bytes().to_vec();
+        let mut chunk_data = b"eXIf".to_vec();
+        chunk_data.extend_from_slice(&exif);
+        chunk.extend_from_slice(&chunk_data);
+        chunk.extend_from_slice(&crc32(&chunk_data).to_be_bytes());
+
+        // the signature (8 bytes) and the header chunk (25 bytes) come first
+        let header_end = 8 + 25;
+        let mut result = png[..header_end].to_vec();
+        result.extend_from_slice(&chunk);
+        result.extend_from_slice(&png[header_end..]);
+        result
+    }
+
+    #[test]
+    fn test_exif_orientation() {
+        // a 2x1 image with a red and a blue pixel, rotated 90 degrees clockwise with the orientation 6
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| match x {
+            0 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 0, 255, 255]),
+        }));
+        let path = env::temp_dir().join(format!("wasi-nn-orientation-{}.png", std::process::id()));
+        fs::write(&path, png_with_orientation(&image, 6)).unwrap();
+        let result = open_image(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+
+        let rgb = to_rgb(&result.unwrap(), BACKGROUND).unwrap();
+        assert_eq!(rgb.dimensions(), (1, 2));
+        assert_eq!(rgb[(0, 0)], Rgb([255, 0, 0]));
+        assert_eq!(rgb[(0, 1)], Rgb([0, 0, 255]));
+    }
+}
@@ -0,0 +1,39 @@
+// The wasi-nn functionality through the witx interface (the wasi-nn crate).
+
+use wasi_nn::{ExecutionTarget, Graph, GraphBuilder, GraphEncoding, GraphExecutionContext, TensorType};
+
+pub type Error = wasi_nn::Error;
+
+
+pub struct Model(Graph);
+
+pub struct Context<'model>(GraphExecutionContext<'model>);
+
+
+pub fn load_model(filename: &str) -> Result<Model, Error> {
+    GraphBuilder::new(
+        GraphEncoding::Onnx,
+        ExecutionTarget::CPU
+    ).build_from_files([filename]).map(Model)
+}
+
+impl Model {
+    pub fn init_execution_context(&self) -> Result<Context<'_>, Error> {
+        self.0.init_execution_context().map(Context)
+    }
+}
+
+impl<'model> Context<'model> {
+    pub fn set_input(&mut self, dimensions: &[usize], data: &[u8]) -> Result<(), Error> {
+        self.0.set_input(0, TensorType::F32, dimensions, data)
+    }
+
+    pub fn compute(&mut self) -> Result<(), Error> {
+        self.0.compute()
+    }
+
+    /// Copies the first output tensor to the buffer and returns its size in bytes.
+    pub fn get_output(&mut self, buffer: &mut [f32]) -> Result<usize, Error> {
+        self.0.get_output(0, buffer)
+    }
+}
@@ -0,0 +1,334 @@
+// The cache of the compiled Wasm modules and components. The compiled code is stored in a cache directory under
+// a name derived from the Wasm file name, a hash of the engine configuration and a hash of the Wasm bytes, so that
+// a rebuilt Wasm file or a different engine configuration is never given the code compiled from another one.
+// Precompiled `.cwasm` files are loaded through the same interface without the cache.
+
+use anyhow::{anyhow, Result};
+use local_names::sha256_bytes;
+use std::{
+    fmt, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+use wasmtime::{component::Component, Engine, Module};
+use engine::EngineOptions;
+
+/// The default cache directory, relative to the working directory.
+pub const DEFAULT_CACHE_DIR: &str = "wasm-cache";
+/// The file extension of the compiled code in the cache.
+const CACHE_EXTENSION: &str = "cwasm";
+/// The number of hexadecimal digits of the hashes used in the cache file names.
+const HASH_LENGTH: usize = 16;
+
+
+/// A Wasm module or component that can be compiled, serialized and loaded back from the serialized code.
+pub trait Compiled: Sized {
+    fn compile(engine: &Engine, wasm_bytes: &[u8]) -> Result<Self>;
+    fn serialize(&self) -> Result<Vec<u8>>;
+    /// Loads the serialized code from the file.
+    ///
+    /// # Safety
+    /// The file must contain code serialized by `serialize`, Wasmtime only checks that the engine is compatible.
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> Result<Self>;
+}
+
+impl Compiled for Module {
+    fn compile(engine: &Engine, wasm_bytes: &[u8]) -> Result<Self> {
+        Module::new(engine, wasm_bytes)
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Module::serialize(self)
+    }
+
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> Result<Self> {
+        Module::deserialize_file(engine, path)
+    }
+}
+
+impl Compiled for Component {
+    fn compile(engine: &Engine, wasm_bytes: &[u8]) -> Result<Self> {
+        Component::new(engine, wasm_bytes)
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Component::serialize(self)
+    }
+
+    unsafe fn deserialize_file(engine: &Engine, path: &Path) -> Result<Self> {
+        Component::deserialize_file(engine, path)
+    }
+}
+
+
+/// How the compiled code was found in the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The compiled code was loaded from the cache.
+    Hit,
+    /// The code was compiled and written to the cache.
+    Miss,
+    /// The cached code could not be loaded, so it was compiled again and replaced.
+    Invalid,
+    /// The cache is disabled and the code was compiled.
+    Disabled,
+    /// The code was loaded from a precompiled file without the cache.
+    Precompiled,
+}
+
+impl fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CacheStatus::Hit => "cache hit",
+            CacheStatus::Miss => "cache miss",
+            CacheStatus::Invalid => "cache miss, invalid entry replaced",
+            CacheStatus::Disabled => "cache disabled",
+            CacheStatus::Precompiled => "precompiled",
+        })
+    }
+}
+
+
+/// Where the compiled code is loaded from.
+enum CacheMode {
+    Directory(PathBuf),
+    Disabled,
+    /// The Wasm file is a precompiled `.cwasm` file, which is loaded as such without compiling anything.
+    Precompiled,
+}
+
+/// The cache directory of the compiled code, or the mode without the cache.
+pub struct ModuleCache {
+    mode: CacheMode,
+}
+
+impl ModuleCache {
+    pub fn new(directory: &str) -> ModuleCache {
+        ModuleCache { mode: CacheMode::Directory(PathBuf::from(directory)) }
+    }
+
+    pub fn disabled() -> ModuleCache {
+        ModuleCache { mode: CacheMode::Disabled }
+    }
+
+    pub fn precompiled() -> ModuleCache {
+        ModuleCache { mode: CacheMode::Precompiled }
+    }
+
+    /// Removes all the compiled code from the cache directory and returns the number of removed files. The temporary
+    /// files left by interrupted writes are also removed but not counted.
+    pub fn clear(&self) -> Result<usize> {
+        let directory = match &self.mode {
+            CacheMode::Directory(directory) if directory.is_dir() => directory,
+            _ => return Ok(0),
+        };
+        let mut removed = 0;
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let is_compiled = path.extension().is_some_and(|extension| extension == CACHE_EXTENSION);
+            if is_compiled || is_temporary_file(&path) {
+                fs::remove_file(&path)
+                    .map_err(|error| anyhow!("Could not remove {}: {}", path.display(), error))?;
+                removed += usize::from(is_compiled);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Loads the compiled code of the Wasm file from the cache, or compiles it and writes it to the cache.
+    /// The cached code of older versions of the Wasm file with the same engine configuration is removed.
+    /// In the precompiled mode the file is loaded as precompiled code and an incompatible file is an error.
+    pub fn load<T: Compiled>(
+        &self,
+        engine: &Engine,
+        engine_options: &EngineOptions,
+        wasm_filename: &str,
+    ) -> Result<(T, CacheStatus)> {
+        if let CacheMode::Precompiled = self.mode {
+            // Wasmtime checks that the code was compiled with the same Wasmtime version and engine settings
+            // and that the host CPU has the features the code was compiled with
+            let compiled = unsafe { T::deserialize_file(engine, Path::new(wasm_filename)) }.map_err(|error| {
+                anyhow!(
+                    "The precompiled file {} is not compatible with this engine ({}): {:#}",
+                    wasm_filename, engine_options, error
+                )
+            })?;
+            return Ok((compiled, CacheStatus::Precompiled));
+        }
+
+        let wasm_bytes = fs::read(wasm_filename)
+            .map_err(|error| anyhow!("Could not read the Wasm file {}: {}", wasm_filename, error))?;
+        let directory = match &self.mode {
+            CacheMode::Directory(directory) => directory,
+            _ => return Ok((T::compile(engine, &wasm_bytes)?, CacheStatus::Disabled)),
+        };
+
+        let prefix = format!("{}.{}.", file_stem(wasm_filename), engine_hash(engine, engine_options));
+        let path = directory.join(format!("{}{}.{}", prefix, short_hash(&wasm_bytes), CACHE_EXTENSION));
+        let status = match path.is_file() {
+            // the file name only tells that the engine configuration and the Wasm bytes match, Wasmtime still
+            // checks that the code was compiled for a compatible engine and an invalid file is replaced
+            true => match unsafe { T::deserialize_file(engine, &path) } {
+                Ok(compiled) => return Ok((compiled, CacheStatus::Hit)),
+                Err(_) => CacheStatus::Invalid,
+            },
+            false => CacheStatus::Miss,
+        };
+
+        let compiled = T::compile(engine, &wasm_bytes)?;
+        remove_entries(directory, &prefix)?;
+        fs::create_dir_all(directory)
+            .map_err(|error| anyhow!("Could not create the cache directory {}: {}", directory.display(), error))?;
+        // written under a temporary name first so that a concurrent run never reads a partially written file
+        let temporary_path = path.with_extension(format!("{}.{}", CACHE_EXTENSION, std::process::id()));
+        fs::write(&temporary_path, compiled.serialize()?)
+            .and_then(|()| fs::rename(&temporary_path, &path))
+            .map_err(|error| anyhow!("Could not write {} to the cache: {}", path.display(), error))?;
+        Ok((compiled, status))
+    }
+}
+
+
+/// The file name of the Wasm file without the `.wasm` extension.
+fn file_stem(wasm_filename: &str) -> String {
+    Path::new(wasm_filename)
+        .file_stem()
+        .map_or_else(|| "module".to_string(), |stem| stem.to_string_lossy().to_string())
+}
+
+/// The hash of the engine configuration. Besides the settings given on the command line, it covers the
+/// compatibility hash of the engine, which includes the Wasmtime version, the target and the compiler flags.
+fn engine_hash(engine: &Engine, engine_options: &EngineOptions) -> String {
+    let mut bytes = ByteHasher(engine_options.to_string().into_bytes());
+    engine.precompile_compatibility_hash().hash(&mut bytes);
+    short_hash(&bytes.0)
+}
+
+fn short_hash(data: &[u8]) -> String {
+    sha256_bytes(data)[..HASH_LENGTH].to_string()
+}
+
+/// Whether the file is a temporary file of the compiled code, named `<entry>.cwasm.<process id>`.
+fn is_temporary_file(path: &Path) -> bool {
+    let has_process_id = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| !extension.is_empty() && extension.bytes().all(|byte| byte.is_ascii_digit()));
+    has_process_id
+        && path
+            .file_stem()
+            .is_some_and(|stem| Path::new(stem).extension().is_some_and(|extension| extension == CACHE_EXTENSION))
+}
+
+/// Removes the cache entries whose names start with the prefix.
+fn remove_entries(directory: &Path, prefix: &str) -> Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let is_entry = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(prefix) && name.ends_with(CACHE_EXTENSION));
+        if is_entry {
+            fs::remove_file(&path).map_err(|error| anyhow!("Could not remove {}: {}", path.display(), error))?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Collects the bytes given to the hasher, for hashing values that only implement `Hash` with SHA-256.
+struct ByteHasher(Vec<u8>);
+
+impl Hasher for ByteHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    const WAT: &str = r#"(module (func (export "run")))"#;
+
+    /// Creates an empty temporary folder for the test.
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("wasmtime-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// The sorted names of the compiled files in the cache directory.
+    fn cache_entries(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(CACHE_EXTENSION))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_load() {
+        let folder = temp_folder("cache-load");
+        let cache_dir = folder.join(DEFAULT_CACHE_DIR);
+        let wasm_file = folder.join("guest.wat");
+        let wasm_filename = wasm_file.to_str().unwrap();
+        let cache = ModuleCache::new(cache_dir.to_str().unwrap());
+        let (engine, engine_options) = (Engine::default(), EngineOptions::default());
+        let load = || cache.load::<Module>(&engine, &engine_options, wasm_filename).unwrap().1;
+
+        fs::write(&wasm_file, WAT).unwrap();
+        assert_eq!(load(), CacheStatus::Miss);
+        assert_eq!(load(), CacheStatus::Hit);
+        let entries = cache_entries(&cache_dir);
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].starts_with("guest."), "{}", entries[0]);
+
+        // the entry of the changed Wasm file replaces the stale one
+        fs::write(&wasm_file, r#"(module (func (export "run")) (memory 1))"#).unwrap();
+        assert_eq!(load(), CacheStatus::Miss);
+        let changed_entries = cache_entries(&cache_dir);
+        assert_eq!(changed_entries.len(), 1);
+        assert_ne!(changed_entries, entries);
+
+        fs::write(cache_dir.join(&changed_entries[0]), b"not compiled code").unwrap();
+        assert_eq!(load(), CacheStatus::Invalid);
+        assert_eq!(load(), CacheStatus::Hit);
+        assert_eq!(cache_entries(&cache_dir), changed_entries);
+
+        let status = ModuleCache::disabled().load::<Module>(&engine, &engine_options, wasm_filename).unwrap().1;
+        assert_eq!(status, CacheStatus::Disabled);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn test_clear() {
+        let folder = temp_folder("cache-clear");
+        for name in ["a.0123.4567.cwasm", "b.0123.4567.cwasm", "c.0123.4567.cwasm.1234", "README.txt", "guest.wasm"] {
+            fs::write(folder.join(name), b"").unwrap();
+        }
+        assert_eq!(ModuleCache::new(folder.to_str().unwrap()).clear().unwrap(), 2);
+
+        let mut remaining: Vec<String> = fs::read_dir(&folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, ["README.txt", "guest.wasm"]);
+
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(ModuleCache::new(folder.to_str().unwrap()).clear().unwrap(), 0);
+    }
+}
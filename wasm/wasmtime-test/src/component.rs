@@ -0,0 +1,135 @@
+// Running the inference with a Wasm component that uses the wasi-nn WIT interface.
+
+use anyhow::{anyhow, Result};
+use std::{fs::File, io::Read, path::Path, time::Instant};
+use wasmtime::{
+    component::{Component, Linker, ResourceTable},
+    Store,
+};
+use wasmtime_wasi::{bindings::sync::Command, I32Exit, WasiCtx, WasiView};
+use wasmtime_wasi_nn::WasiNnCtx;
+use cache::CacheStatus;
+use ctx::{wasi_nn_ctx, WasiConfig};
+use engine::GuestEngine;
+use invoke::{self, Invocation};
+use limits::{GuestLimiter, HasLimiter};
+
+/// The default function called in components, exported by the inference test component.
+pub const FUNCTION_NAME: &str = "run-inference";
+
+
+/// The host state for running wasi-nn tests with components (WASI preview 2 and wasi-nn WIT).
+struct ComponentCtx {
+    wasi: WasiCtx,
+    table: ResourceTable,
+    wasi_nn: WasiNnCtx,
+    limiter: GuestLimiter,
+}
+impl ComponentCtx {
+    fn new(config: &WasiConfig, limiter: GuestLimiter) -> Result<Self> {
+        let wasi = config.builder()?.build();
+        let wasi_nn = wasi_nn_ctx();
+
+        Ok(Self { wasi, table: ResourceTable::new(), wasi_nn, limiter })
+    }
+}
+impl HasLimiter for ComponentCtx {
+    fn limiter(&mut self) -> &mut GuestLimiter {
+        &mut self.limiter
+    }
+}
+impl WasiView for ComponentCtx {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+
+/// Checks from the Wasm binary header whether the given file is a component instead of a core module.
+pub fn is_component(filename: &str) -> Result<bool> {
+    const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+    const COMPONENT_LAYER: [u8; 2] = [0x01, 0x00];
+
+    let mut header = [0u8; 8];
+    File::open(Path::new(filename))
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|error| anyhow!("Could not read the Wasm file {}: {}", filename, error))?;
+    if header[0..4] != WASM_MAGIC {
+        return Err(anyhow!("Not a Wasm binary: {}", filename));
+    }
+
+    Ok(header[6..8] == COMPONENT_LAYER)
+}
+
+/// Runs a component and returns the process exit code. The creation of the Wasm environment is timed from `start`.
+/// The compiled component is loaded from the cache or compiled and written to it. The called function is run with the limits.
+pub fn run(
+    start: Instant,
+    guest_engine: &GuestEngine,
+    wasm_component_filename: &str,
+    wasi_config: &WasiConfig,
+    invocation: &Invocation,
+) -> Result<i32> {
+    let GuestEngine { engine, options: engine_options, cache, limits } = *guest_engine;
+    let mut linker = Linker::new(engine);
+    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+    wasmtime_wasi_nn::wit::ML::add_to_linker(&mut linker, |host: &mut ComponentCtx| &mut host.wasi_nn)?;
+
+    let mut store = Store::new(
+        engine,
+        ComponentCtx::new(wasi_config, limits.limiter())?
+    );
+    limits.configure_store(&mut store)?;
+    let environment_set_time = start.elapsed();
+
+    let (wasm_component, cache_status): (Component, CacheStatus) =
+        cache.load(engine, engine_options, wasm_component_filename)?;
+
+    let instance = linker.instantiate(&mut store, &wasm_component)?;
+    let module_load_time = start.elapsed() - environment_set_time;
+
+    let print_load_times = |function_load_time| {
+        println!("Creating the Wasm environment took: {:?}", environment_set_time);
+        println!("Loading the Wasm component took: {:?} ({})", module_load_time, cache_status);
+        println!("Loading the Wasm function took: {:?}\n", function_load_time);
+    };
+
+    match invocation {
+        Invocation::Function { name, args } => {
+            let inference_function = instance
+                .get_func(&mut store, name.as_str())
+                .ok_or_else(|| anyhow!(
+                    "The Wasm component {} does not export a function named {}", wasm_component_filename, name
+                ))?;
+            print_load_times(start.elapsed() - environment_set_time - module_load_time);
+
+            let result = limits.run(engine, &mut store, |store| {
+                invoke::call_component_function(store, &inference_function, name, args)
+            })?;
+            Ok(invoke::function_exit_code(name, result))
+        }
+        Invocation::Command => {
+            let command = Command::new(&mut store, &instance)
+                .map_err(|error| anyhow!("The Wasm component {} is not a WASI command: {}", wasm_component_filename, error))?;
+            print_load_times(start.elapsed() - environment_set_time - module_load_time);
+
+            // WASI preview 2 only tells whether the command succeeded, so any failure exits with the status 1
+            let result = limits.run(engine, &mut store, |store| {
+                command.wasi_cli_run().call_run(store).and_then(|result| result.map_err(|()| anyhow!(I32Exit(1))))
+            });
+            invoke::command_exit_code(result)
+        }
+        Invocation::Json { model, image, options } => {
+            print_load_times(start.elapsed() - environment_set_time - module_load_time);
+
+            let result_json = limits.run(engine, &mut store, |store| {
+                invoke::call_component_json_function(store, &instance, model, image, options)
+            })?;
+            invoke::json_exit_code(&result_json)
+        }
+    }
+}
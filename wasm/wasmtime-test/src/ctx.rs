@@ -0,0 +1,100 @@
+// The host state for running wasi-nn tests with core Wasm modules and the shared WASI configuration.
+
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use wasmtime_wasi::{preview1::WasiP1Ctx, DirPerms, FilePerms, WasiCtxBuilder};
+use wasmtime_wasi_nn::{InMemoryRegistry, WasiNnCtx};
+use wasi_nn_onnx_backend::OnnxBackend;
+use limits::{GuestLimiter, HasLimiter};
+use trap::GuestStderr;
+
+
+/// A host directory that is shared with the guest.
+#[derive(Clone, Debug)]
+pub struct Preopen {
+    pub host_path: String,
+    pub guest_path: String,
+    pub read_only: bool,
+}
+impl Preopen {
+    pub fn read_only(host_path: &str, guest_path: &str) -> Self {
+        Self { host_path: host_path.to_string(), guest_path: guest_path.to_string(), read_only: true }
+    }
+
+    pub fn read_write(host_path: &str, guest_path: &str) -> Self {
+        Self { host_path: host_path.to_string(), guest_path: guest_path.to_string(), read_only: false }
+    }
+
+    fn permissions(&self) -> (DirPerms, FilePerms) {
+        match self.read_only {
+            true => (DirPerms::READ, FilePerms::READ),
+            false => (DirPerms::all(), FilePerms::all()),
+        }
+    }
+}
+
+
+/// The WASI configuration given to the guest: shared directories, command line arguments, environment variables
+/// and the stderr that keeps the end of the guest output for the trap reports.
+pub struct WasiConfig<'a> {
+    pub preopens: &'a [Preopen],
+    pub args: &'a [String],
+    pub envs: &'a [(String, String)],
+    pub stderr: &'a GuestStderr,
+}
+impl WasiConfig<'_> {
+    /// Creates a WASI context builder with this configuration and with the stdin and stdout inherited from the host.
+    pub fn builder(&self) -> Result<WasiCtxBuilder> {
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .inherit_stdio()
+            .stderr(self.stderr.clone())
+            .args(self.args)
+            .envs(self.envs);
+
+        for preopen in self.preopens {
+            if !Path::new(&preopen.host_path).is_dir() {
+                return Err(anyhow!(
+                    "Directory to share with the guest does not exist: {} (the program should be run from the bin folder)",
+                    preopen.host_path
+                ));
+            }
+            let (dir_perms, file_perms) = preopen.permissions();
+            builder
+                .preopened_dir(&preopen.host_path, &preopen.guest_path, dir_perms, file_perms)
+                .map_err(|error| anyhow!("Could not share the directory {} with the guest: {}", preopen.host_path, error))?;
+        }
+
+        Ok(builder)
+    }
+}
+
+
+/// Creates the wasi-nn context with the ONNX backend.
+pub fn wasi_nn_ctx() -> WasiNnCtx {
+    WasiNnCtx::new(
+        [OnnxBackend::default().into()],
+        InMemoryRegistry::new().into()
+    )
+}
+
+
+/// The host state for running wasi-nn tests with core Wasm modules (WASI preview 1 and wasi-nn witx).
+pub struct Ctx {
+    pub wasi: WasiP1Ctx,
+    pub wasi_nn: WasiNnCtx,
+    pub limiter: GuestLimiter,
+}
+impl Ctx {
+    pub fn new(config: &WasiConfig, limiter: GuestLimiter) -> Result<Self> {
+        let wasi = config.builder()?.build_p1();
+        let wasi_nn = wasi_nn_ctx();
+
+        Ok(Self { wasi, wasi_nn, limiter })
+    }
+}
+impl HasLimiter for Ctx {
+    fn limiter(&mut self) -> &mut GuestLimiter {
+        &mut self.limiter
+    }
+}
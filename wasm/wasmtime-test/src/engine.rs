@@ -0,0 +1,275 @@
+// The configuration of the Wasmtime engine: the compiler and its optimization level, the SIMD proposals,
+// the linear memory reservation and guard sizes, parallel compilation and NaN canonicalization, the target
+// and CPU features for precompiling the guests for other devices and the details of the Wasm backtraces.
+// The configuration is printed with the benchmark output so that the timings with different engine settings
+// can be compared.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use wasmtime::{Config, Engine, OptLevel, Strategy, WasmBacktraceDetails};
+use cache::ModuleCache;
+use limits::RunLimits;
+
+
+/// The code generator used for compiling the Wasm code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compiler {
+    Cranelift,
+    Winch,
+}
+
+impl Compiler {
+    pub fn from_name(name: &str) -> Result<Compiler> {
+        match name {
+            "cranelift" => Ok(Compiler::Cranelift),
+            "winch" => Ok(Compiler::Winch),
+            _ => Err(anyhow!("Unknown compiler: {} (expected cranelift or winch)", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compiler::Cranelift => "cranelift",
+            Compiler::Winch => "winch",
+        }
+    }
+}
+
+
+/// The engine settings given on the command line. The settings that are not given use the Wasmtime defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOptions {
+    pub compiler: Compiler,
+    /// The Cranelift optimization level, not used by Winch.
+    pub opt_level: OptLevel,
+    pub simd: bool,
+    pub relaxed_simd: bool,
+    /// The maximum size of a linear memory that is reserved up front (static memory) in bytes. With 0 all
+    /// the memories are dynamic and the accesses are bounds-checked explicitly.
+    pub memory_reservation: Option<u64>,
+    /// The size of the guard region after the linear memories in bytes.
+    pub memory_guard_size: Option<u64>,
+    pub parallel_compilation: bool,
+    /// Whether the NaN values are canonicalized by Cranelift, not used by Winch.
+    pub nan_canonicalization: bool,
+    /// The target triple that the code is compiled for, the host when not given.
+    pub target: Option<String>,
+    /// The Cranelift CPU feature flags enabled for the target, for example `has_avx2` or `has_lse`.
+    /// When compiling for the host without features, the features of the host CPU are detected.
+    pub cpu_features: Vec<String>,
+    /// Whether the compiled code checks the epoch for the timeouts.
+    pub epoch_interruption: bool,
+    /// Whether the compiled code counts the fuel consumed by the guest.
+    pub consume_fuel: bool,
+    /// Whether the Wasm backtraces have the source files and lines from the DWARF debug information of the guest.
+    pub backtrace_details: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            compiler: Compiler::Cranelift,
+            opt_level: OptLevel::Speed,
+            simd: true,
+            relaxed_simd: true,
+            memory_reservation: None,
+            memory_guard_size: None,
+            parallel_compilation: true,
+            nan_canonicalization: false,
+            target: None,
+            cpu_features: Vec::new(),
+            epoch_interruption: false,
+            consume_fuel: false,
+            backtrace_details: false,
+        }
+    }
+}
+
+impl EngineOptions {
+    /// The Wasmtime configuration with these settings and the component model enabled.
+    pub fn config(&self) -> Result<Config> {
+        let mut config = Config::default();
+        config
+            .wasm_component_model(true)
+            .strategy(match self.compiler {
+                Compiler::Cranelift => Strategy::Cranelift,
+                Compiler::Winch => Strategy::Winch,
+            })
+            .cranelift_opt_level(self.opt_level)
+            .wasm_simd(self.simd)
+            // relaxed SIMD cannot be enabled without SIMD
+            .wasm_relaxed_simd(self.simd && self.relaxed_simd)
+            .parallel_compilation(self.parallel_compilation)
+            .cranelift_nan_canonicalization(self.nan_canonicalization)
+            .epoch_interruption(self.epoch_interruption)
+            .consume_fuel(self.consume_fuel)
+            .wasm_backtrace_details(match self.backtrace_details {
+                true => WasmBacktraceDetails::Enable,
+                false => WasmBacktraceDetails::Disable,
+            });
+        if let Some(size) = self.memory_reservation {
+            config.static_memory_maximum_size(size);
+        }
+        if let Some(size) = self.memory_guard_size {
+            config.static_memory_guard_size(size).dynamic_memory_guard_size(size);
+        }
+        if let Some(target) = &self.target {
+            config.target(target)?;
+        }
+        for feature in self.cpu_features.iter() {
+            // the unknown flags are reported when the engine is created, and Wasmtime checks that the host
+            // supports the enabled features before running code compiled with them
+            unsafe {
+                config.cranelift_flag_enable(feature);
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// The settings on one line, for example `compiler=cranelift opt-level=speed simd=on relaxed-simd=on
+/// memory-reservation=default memory-guard-size=default parallel-compilation=on nan-canonicalization=off`.
+/// The target, the CPU features, the epoch interruption, the fuel and the backtrace details are only included
+/// when they are given.
+impl fmt::Display for EngineOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        let size = |size: Option<u64>| size.map_or_else(|| "default".to_string(), |size| size.to_string());
+        write!(
+            f,
+            "compiler={} opt-level={} simd={} relaxed-simd={} memory-reservation={} memory-guard-size={} \
+            parallel-compilation={} nan-canonicalization={}",
+            self.compiler.name(),
+            opt_level_name(self.opt_level),
+            on_off(self.simd),
+            on_off(self.simd && self.relaxed_simd),
+            size(self.memory_reservation),
+            size(self.memory_guard_size),
+            on_off(self.parallel_compilation),
+            on_off(self.nan_canonicalization)
+        )?;
+        if let Some(target) = &self.target {
+            write!(f, " target={}", target)?;
+        }
+        if !self.cpu_features.is_empty() {
+            write!(f, " cpu-features={}", self.cpu_features.join(","))?;
+        }
+        if self.epoch_interruption {
+            write!(f, " epoch-interruption=on")?;
+        }
+        if self.consume_fuel {
+            write!(f, " fuel=on")?;
+        }
+        if self.backtrace_details {
+            write!(f, " backtrace-details=on")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// The engine for running a guest with the settings it was created with, the cache of the compiled code and
+/// the limits of the called guest function.
+pub struct GuestEngine<'a> {
+    pub engine: &'a Engine,
+    pub options: &'a EngineOptions,
+    pub cache: &'a ModuleCache,
+    pub limits: &'a RunLimits,
+}
+
+
+pub fn parse_opt_level(name: &str) -> Result<OptLevel> {
+    match name {
+        "none" => Ok(OptLevel::None),
+        "speed" => Ok(OptLevel::Speed),
+        "speed_and_size" => Ok(OptLevel::SpeedAndSize),
+        _ => Err(anyhow!("Unknown optimization level: {} (expected none, speed or speed_and_size)", name)),
+    }
+}
+
+fn opt_level_name(opt_level: OptLevel) -> &'static str {
+    match opt_level {
+        OptLevel::None => "none",
+        OptLevel::Speed => "speed",
+        OptLevel::SpeedAndSize => "speed_and_size",
+        _ => "unknown",
+    }
+}
+
+/// Parses a size in bytes with an optional `K`, `M` or `G` suffix (powers of 1024), for example `4G`.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'K')) | Some((index, 'k')) => (&value[..index], 1 << 10),
+        Some((index, 'M')) | Some((index, 'm')) => (&value[..index], 1 << 20),
+        Some((index, 'G')) | Some((index, 'g')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| anyhow!("Invalid size: {}", value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("65536").unwrap(), 65536);
+        assert_eq!(parse_size("64K").unwrap(), 64 << 10);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size("2M").unwrap(), 2 << 20);
+        assert_eq!(parse_size("2m").unwrap(), 2 << 20);
+        assert_eq!(parse_size("4G").unwrap(), 4 << 30);
+        assert_eq!(parse_size("4g").unwrap(), 4 << 30);
+        assert_eq!(parse_size("17179869183G").unwrap(), 17179869183 << 30);
+
+        // the last one overflows in the multiplication
+        for invalid in ["", "K", "-1", "1.5G", "1T", "4 G", "18446744073709551616", "17179869184G"] {
+            assert!(parse_size(invalid).is_err(), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_opt_level() {
+        for name in ["none", "speed", "speed_and_size"] {
+            assert_eq!(opt_level_name(parse_opt_level(name).unwrap()), name);
+        }
+        assert!(parse_opt_level("").is_err());
+        assert!(parse_opt_level("Speed").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        // the default configuration is documented in the README and the string is also hashed into the cache keys
+        let default = EngineOptions::default().to_string();
+        assert_eq!(
+            default,
+            "compiler=cranelift opt-level=speed simd=on relaxed-simd=on memory-reservation=default \
+            memory-guard-size=default parallel-compilation=on nan-canonicalization=off"
+        );
+        assert!(include_str!("../../../README.md").contains(&format!("`Engine configuration: {}`", default)));
+
+        let options = EngineOptions {
+            compiler: Compiler::Winch,
+            opt_level: OptLevel::None,
+            simd: false,
+            memory_reservation: Some(0),
+            memory_guard_size: Some(64 << 10),
+            target: Some("aarch64-unknown-linux-gnu".to_string()),
+            cpu_features: vec!["has_lse".to_string(), "has_fp16".to_string()],
+            consume_fuel: true,
+            ..EngineOptions::default()
+        };
+        assert_eq!(
+            options.to_string(),
+            "compiler=winch opt-level=none simd=off relaxed-simd=off memory-reservation=0 memory-guard-size=65536 \
+            parallel-compilation=on nan-canonicalization=off target=aarch64-unknown-linux-gnu \
+            cpu-features=has_lse,has_fp16 fuel=on"
+        );
+    }
+}
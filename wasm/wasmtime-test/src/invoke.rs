@@ -0,0 +1,255 @@
+// Calling an exported Wasm function with arguments given as strings on the command line.
+
+use anyhow::{anyhow, Result};
+use local_names::{get_exit_code, ErrorType, InferenceResult};
+use local_names::string_abi::{self, ALLOCATE_FUNCTION_NAME, DEALLOCATE_FUNCTION_NAME, INFERENCE_FUNCTION_NAME};
+use wasmtime::{component, AsContextMut, Func, Instance, Memory, TypedFunc, Val, ValType};
+use wasmtime_wasi::I32Exit;
+
+
+/// How the guest is run: by calling an exported function with arguments or as a WASI command.
+pub enum Invocation {
+    Function { name: String, args: Vec<String> },
+    Command,
+    /// The string passing ABI: the model and image paths and the JSON options are written to the guest memory.
+    Json { model: String, image: String, options: String },
+}
+
+/// The name of the memory exported by core Wasm modules.
+const MEMORY_NAME: &str = "memory";
+/// The name of the component function that takes the paths and options as strings and returns the JSON result.
+pub const COMPONENT_JSON_FUNCTION_NAME: &str = "run-inference-json";
+
+
+/// The exports of a core Wasm module needed for the string passing ABI.
+pub struct JsonFunctions {
+    memory: Memory,
+    allocate: TypedFunc<i32, i32>,
+    deallocate: TypedFunc<(i32, i32), ()>,
+    inference: TypedFunc<(i32, i32, i32, i32, i32, i32), i64>,
+}
+
+impl JsonFunctions {
+    pub fn new(mut store: impl AsContextMut, instance: &Instance) -> Result<Self> {
+        let memory = instance
+            .get_memory(&mut store, MEMORY_NAME)
+            .ok_or_else(|| anyhow!("The Wasm module does not export a memory named {}", MEMORY_NAME))?;
+        let allocate = instance
+            .get_typed_func(&mut store, ALLOCATE_FUNCTION_NAME)
+            .map_err(|error| anyhow!("Could not load the allocator {}: {}", ALLOCATE_FUNCTION_NAME, error))?;
+        let deallocate = instance
+            .get_typed_func(&mut store, DEALLOCATE_FUNCTION_NAME)
+            .map_err(|error| anyhow!("Could not load the deallocator {}: {}", DEALLOCATE_FUNCTION_NAME, error))?;
+        let inference = instance
+            .get_typed_func(&mut store, INFERENCE_FUNCTION_NAME)
+            .map_err(|error| anyhow!("Could not load the function {}: {}", INFERENCE_FUNCTION_NAME, error))?;
+
+        Ok(Self { memory, allocate, deallocate, inference })
+    }
+
+    /// Writes the strings to the guest memory, runs the inference and returns the JSON result read from the guest memory.
+    pub fn call(&self, mut store: impl AsContextMut, model: &str, image: &str, options: &str) -> Result<String> {
+        let model_pointer = self.write_string(&mut store, model)?;
+        let image_pointer = self.write_string(&mut store, image)?;
+        let options_pointer = self.write_string(&mut store, options)?;
+
+        let packed_result = self.inference.call(
+            &mut store,
+            (model_pointer, model.len() as i32, image_pointer, image.len() as i32, options_pointer, options.len() as i32)
+        )?;
+        let (result_pointer, result_length) = string_abi::unpack_pointer(packed_result as u64);
+        if result_pointer == 0 {
+            return Err(anyhow!("The function {} did not return a result", INFERENCE_FUNCTION_NAME));
+        }
+        let mut result_bytes = vec![0u8; result_length as usize];
+        self.memory.read(&store, result_pointer as usize, &mut result_bytes)?;
+
+        self.deallocate.call(&mut store, (result_pointer as i32, result_length as i32))?;
+        self.deallocate.call(&mut store, (options_pointer, options.len() as i32))?;
+        self.deallocate.call(&mut store, (image_pointer, image.len() as i32))?;
+        self.deallocate.call(&mut store, (model_pointer, model.len() as i32))?;
+
+        String::from_utf8(result_bytes).map_err(|error| anyhow!("The result is not valid UTF-8: {}", error))
+    }
+
+    /// Allocates memory from the guest and writes the string there. Returns the pointer to the string.
+    fn write_string(&self, mut store: impl AsContextMut, value: &str) -> Result<i32> {
+        let pointer = self.allocate.call(&mut store, value.len() as i32)?;
+        if pointer == 0 {
+            return Err(anyhow!("Could not allocate {} bytes from the guest memory", value.len()));
+        }
+        self.memory.write(&mut store, pointer as u32 as usize, value.as_bytes())?;
+        Ok(pointer)
+    }
+}
+
+
+/// Calls the component function that takes the paths and options as strings and returns the JSON result.
+pub fn call_component_json_function(
+    mut store: impl AsContextMut,
+    instance: &component::Instance,
+    model: &str,
+    image: &str,
+    options: &str,
+) -> Result<String> {
+    let function = instance
+        .get_typed_func::<(&str, &str, &str), (String,)>(&mut store, COMPONENT_JSON_FUNCTION_NAME)
+        .map_err(|error| anyhow!("Could not load the function {}: {}", COMPONENT_JSON_FUNCTION_NAME, error))?;
+    let (result,) = function.call(&mut store, (model, image, options))?;
+    function.post_return(&mut store)?;
+
+    Ok(result)
+}
+
+
+/// Calls a core Wasm function with the given arguments and returns the i32 result.
+/// The arguments are parsed according to the function type and the function must return a single i32.
+pub fn call_core_function(mut store: impl AsContextMut, function: &Func, name: &str, args: &[String]) -> Result<i32> {
+    let function_type = function.ty(&store);
+    let param_types: Vec<ValType> = function_type.params().collect();
+    let result_types: Vec<ValType> = function_type.results().collect();
+    let signature = format!("{:?} -> {:?}", param_types, result_types);
+
+    if param_types.len() != args.len() {
+        return Err(anyhow!(
+            "Function {} has the type {} but {} arguments were given", name, signature, args.len()
+        ));
+    }
+    match result_types.as_slice() {
+        [ValType::I32] => (),
+        _ => return Err(anyhow!("Function {} has the type {} but it should return a single i32", name, signature)),
+    }
+
+    let params = param_types
+        .iter()
+        .zip(args)
+        .enumerate()
+        .map(|(index, (param_type, arg))| {
+            let value = match param_type {
+                ValType::I32 => parse_i32(arg).map(Val::I32),
+                ValType::I64 => parse_i64(arg).map(Val::I64),
+                ValType::F32 => arg.parse::<f32>().ok().map(|value| Val::F32(value.to_bits())),
+                ValType::F64 => arg.parse::<f64>().ok().map(|value| Val::F64(value.to_bits())),
+                _ => None,
+            };
+            value.ok_or_else(|| anyhow!(
+                "Invalid argument {} for function {} with the type {}: '{}' is not a valid {}",
+                index + 1, name, signature, arg, param_type
+            ))
+        })
+        .collect::<Result<Vec<Val>>>()?;
+
+    let mut results = [Val::I32(0)];
+    function.call(&mut store, &params, &mut results)?;
+
+    match results[0] {
+        Val::I32(value) => Ok(value),
+        _ => Err(anyhow!("Function {} did not return an i32", name)),
+    }
+}
+
+/// Calls a component function with the given arguments and returns the s32 result.
+/// The arguments are parsed according to the function type and the function must return a single s32.
+pub fn call_component_function(mut store: impl AsContextMut, function: &component::Func, name: &str, args: &[String]) -> Result<i32> {
+    let param_types = function.params(&store);
+    let result_types = function.results(&store);
+    let signature = format!("{:?} -> {:?}", param_types, result_types);
+
+    if param_types.len() != args.len() {
+        return Err(anyhow!(
+            "Function {} has the type {} but {} arguments were given", name, signature, args.len()
+        ));
+    }
+    match &*result_types {
+        [component::Type::S32] => (),
+        _ => return Err(anyhow!("Function {} has the type {} but it should return a single s32", name, signature)),
+    }
+
+    let params = param_types
+        .iter()
+        .zip(args)
+        .enumerate()
+        .map(|(index, (param_type, arg))| {
+            let value = match param_type {
+                component::Type::S32 => arg.parse().ok().map(component::Val::S32),
+                component::Type::U32 => arg.parse().ok().map(component::Val::U32),
+                component::Type::S64 => arg.parse().ok().map(component::Val::S64),
+                component::Type::U64 => arg.parse().ok().map(component::Val::U64),
+                component::Type::Float32 => arg.parse().ok().map(component::Val::Float32),
+                component::Type::Float64 => arg.parse().ok().map(component::Val::Float64),
+                component::Type::Bool => arg.parse().ok().map(component::Val::Bool),
+                component::Type::String => Some(component::Val::String(arg.clone())),
+                _ => None,
+            };
+            value.ok_or_else(|| anyhow!(
+                "Invalid argument {} for function {} with the type {}: '{}' is not a valid {:?}",
+                index + 1, name, signature, arg, param_type
+            ))
+        })
+        .collect::<Result<Vec<component::Val>>>()?;
+
+    let mut results = [component::Val::S32(0)];
+    function.call(&mut store, &params, &mut results)?;
+    function.post_return(&mut store)?;
+
+    match results[0] {
+        component::Val::S32(value) => Ok(value),
+        _ => Err(anyhow!("Function {} did not return an s32", name)),
+    }
+}
+
+
+/// Prints the value returned by the called function and converts it to the process exit code.
+/// Negative values are error codes from the guest and exit with the absolute value, other values exit with 0.
+pub fn function_exit_code(function_name: &str, result: i32) -> i32 {
+    match ErrorType::from_code(result) {
+        Some(error) => println!("{} returned the error {}", function_name, error),
+        None if result < 0 => println!("{} returned an unknown error code {}", function_name, result),
+        None => println!("{} returned {}", function_name, result),
+    }
+    get_exit_code(result)
+}
+
+/// Prints the JSON result returned by the guest and converts it to the process exit code like `function_exit_code`.
+pub fn json_exit_code(result_json: &str) -> Result<i32> {
+    println!("{}", result_json);
+    let result = InferenceResult::from_json(result_json).map_err(|error| anyhow!("{}", error))?;
+    if let Some(error) = &result.error {
+        match &error.message {
+            Some(message) => println!("The guest returned the error {} ({}): {}: {}", error.name, error.code, error.description, message),
+            None => println!("The guest returned the error {} ({}): {}", error.name, error.code, error.description),
+        }
+    }
+    Ok(get_exit_code(result.code()))
+}
+
+/// Converts the result of running a WASI command to the process exit code.
+/// An exit with a non-zero status is printed and decoded as an error code from the guest.
+pub fn command_exit_code(result: Result<()>) -> Result<i32> {
+    match result {
+        Ok(()) => Ok(0),
+        Err(error) => match error.downcast_ref::<I32Exit>().map(|exit| exit.0) {
+            Some(0) => Ok(0),
+            Some(status) => {
+                match ErrorType::from_code(-status) {
+                    Some(error) => println!("The guest exited with the error {}", error),
+                    None => println!("The guest exited with the status {}", status),
+                }
+                Ok(status)
+            }
+            None => Err(error),
+        },
+    }
+}
+
+
+/// Parses an i32 argument, also accepting the full u32 range since Wasm integers have no sign
+/// (for example the number of repeats is an u32 in the guest code).
+fn parse_i32(value: &str) -> Option<i32> {
+    value.parse::<i32>().ok().or_else(|| value.parse::<u32>().ok().map(|unsigned| unsigned as i32))
+}
+
+/// Parses an i64 argument, also accepting the full u64 range.
+fn parse_i64(value: &str) -> Option<i64> {
+    value.parse::<i64>().ok().or_else(|| value.parse::<u64>().ok().map(|unsigned| unsigned as i64))
+}
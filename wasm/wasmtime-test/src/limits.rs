@@ -0,0 +1,307 @@
+// The limits of running the guest: a wall-clock timeout through epoch interruption, fuel metering, which
+// counts the executed Wasm instructions as a deterministic cost of the inference and can limit it, and the limits
+// of the guest linear memory, tables and instances, with the peak linear memory of the guest reported after the run.
+
+use anyhow::{anyhow, Error, Result};
+use std::{cell::Cell, thread, time::Duration};
+use wasmtime::{Engine, ResourceLimiter, Store, DEFAULT_INSTANCE_LIMIT, DEFAULT_TABLE_LIMIT};
+
+
+/// The timeout, fuel and resource limits given on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct RunLimits {
+    /// The wall-clock time the called guest function may run before it is interrupted.
+    pub timeout: Option<Duration>,
+    /// Whether the fuel consumed by the called guest function is measured.
+    pub measure_fuel: bool,
+    /// Whether the fuel of a single inference is measured by running the guest again with 0 repeats.
+    pub fuel_per_inference: bool,
+    /// The fuel available for the guest, which also turns on the measuring.
+    pub fuel_limit: Option<u64>,
+    /// The maximum size of each linear memory in bytes.
+    pub max_memory: Option<u64>,
+    /// The maximum number of elements in each table.
+    pub max_table_elements: Option<u32>,
+    /// The maximum number of instances, the Wasmtime default when not given.
+    pub max_instances: Option<usize>,
+    /// The maximum number of tables, the Wasmtime default when not given.
+    pub max_tables: Option<usize>,
+    /// The fuel consumed by the last call run with the limits, `None` when the fuel is not metered.
+    pub fuel_consumed: Cell<Option<u64>>,
+}
+
+impl RunLimits {
+    /// The fuel given to the guest, `None` when the fuel is not metered.
+    pub fn fuel(&self) -> Option<u64> {
+        match (self.fuel_limit, self.measure_fuel) {
+            (Some(limit), _) => Some(limit),
+            (None, true) => Some(u64::MAX),
+            (None, false) => None,
+        }
+    }
+
+    /// The resource limiter for the host state of the guest.
+    pub fn limiter(&self) -> GuestLimiter {
+        GuestLimiter {
+            max_memory: self.max_memory,
+            max_table_elements: self.max_table_elements,
+            max_instances: self.max_instances.unwrap_or(DEFAULT_INSTANCE_LIMIT),
+            max_tables: self.max_tables.unwrap_or(DEFAULT_TABLE_LIMIT),
+            memory: 0,
+            pending_growth: 0,
+            peak_memory: 0,
+            peak_before_growth: 0,
+            denied: None,
+        }
+    }
+
+    /// Sets the resource limiter of the host state, gives the fuel to the store and sets the epoch deadline,
+    /// which is reached when the timer of the call expires.
+    pub fn configure_store<T: HasLimiter + 'static>(&self, store: &mut Store<T>) -> Result<()> {
+        store.limiter(|host| host.limiter());
+        if let Some(fuel) = self.fuel() {
+            store.set_fuel(fuel)?;
+        }
+        if self.timeout.is_some() {
+            store.set_epoch_deadline(1);
+            store.epoch_deadline_trap();
+        }
+        Ok(())
+    }
+
+    /// Calls the guest with the timeout running and prints the fuel consumed by the call and the peak linear memory
+    /// of the guest, also when the call fails.
+    pub fn run<T: HasLimiter, R, F>(&self, engine: &Engine, store: &mut Store<T>, call: F) -> Result<R>
+    where F: FnOnce(&mut Store<T>) -> Result<R> {
+        if let Some(timeout) = self.timeout {
+            // the timer thread is left sleeping if the call returns earlier, the process exits after the call
+            let engine = engine.clone();
+            thread::spawn(move || {
+                thread::sleep(timeout);
+                engine.increment_epoch();
+            });
+        }
+        let fuel_before = store.get_fuel().ok();
+        let result = call(store);
+        if let (Some(before), Ok(after)) = (fuel_before, store.get_fuel()) {
+            println!("Fuel consumed by the guest: {}", before - after);
+            self.fuel_consumed.set(Some(before - after));
+        }
+        store.data_mut().limiter().print_report();
+        result
+    }
+}
+
+
+/// The host state of the guest that owns its resource limiter.
+pub trait HasLimiter {
+    fn limiter(&mut self) -> &mut GuestLimiter;
+}
+
+/// The resource limiter of the guest, which also tracks the peak size of its linear memories.
+pub struct GuestLimiter {
+    max_memory: Option<u64>,
+    max_table_elements: Option<u32>,
+    max_instances: usize,
+    max_tables: usize,
+    /// The total size of the linear memories in bytes.
+    memory: usize,
+    /// The growth of the memory size that was allowed last, undone if the growing fails.
+    pending_growth: usize,
+    peak_memory: usize,
+    /// The peak before the growth that was allowed last, restored if the growing fails.
+    peak_before_growth: usize,
+    /// The first growth of a memory or a table that was denied by the limits.
+    denied: Option<String>,
+}
+
+impl GuestLimiter {
+    fn print_report(&self) {
+        println!(
+            "Peak guest linear memory: {:.1} MiB ({} bytes)",
+            self.peak_memory as f64 / (1 << 20) as f64, self.peak_memory
+        );
+        if let Some(denied) = &self.denied {
+            println!("The guest was denied {}", denied);
+        }
+    }
+}
+
+impl ResourceLimiter for GuestLimiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        if let Some(max_memory) = self.max_memory {
+            if desired as u64 > max_memory {
+                self.denied.get_or_insert_with(|| format!(
+                    "growing a linear memory from {} to {} bytes over the limit of {} bytes",
+                    current, desired, max_memory
+                ));
+                return Ok(false);
+            }
+        }
+        self.pending_growth = desired - current;
+        self.memory += self.pending_growth;
+        self.peak_before_growth = self.peak_memory;
+        self.peak_memory = self.peak_memory.max(self.memory);
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, _error: Error) -> Result<()> {
+        self.memory -= self.pending_growth;
+        self.peak_memory = self.peak_before_growth;
+        self.pending_growth = 0;
+        Ok(())
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, _maximum: Option<u32>) -> Result<bool> {
+        if let Some(max_table_elements) = self.max_table_elements {
+            if desired > max_table_elements {
+                self.denied.get_or_insert_with(|| format!(
+                    "growing a table from {} to {} elements over the limit of {} elements",
+                    current, desired, max_table_elements
+                ));
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+    }
+
+    fn tables(&self) -> usize {
+        self.max_tables
+    }
+}
+
+
+/// The fuel of a single inference from the fuel of a run with `repeats` repeats and the fuel of a run with 0 repeats.
+/// Both runs load the model and run the first inference, so the difference is the fuel of the repeated inferences.
+pub fn fuel_per_inference(fuel: u64, fuel_without_repeats: u64, repeats: u32) -> Option<u64> {
+    match repeats {
+        0 => None,
+        _ => fuel.checked_sub(fuel_without_repeats).map(|fuel| fuel / repeats as u64),
+    }
+}
+
+
+/// Parses a duration with an optional `ms`, `s` or `m` suffix, seconds without a suffix, for example `30s` or `500ms`.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let (number, milliseconds) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1000)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60 * 1000)
+    } else {
+        (value, 1000)
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(milliseconds))
+        .filter(|milliseconds| *milliseconds > 0)
+        .map(Duration::from_millis)
+        .ok_or_else(|| anyhow!("Invalid duration: {}", value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trap::{report_trap, GuestStderr, EXIT_OUT_OF_FUEL, EXIT_TIMEOUT};
+    use wasmtime::{Config, Instance, Module};
+
+    struct Host {
+        limiter: GuestLimiter,
+    }
+
+    impl HasLimiter for Host {
+        fn limiter(&mut self) -> &mut GuestLimiter {
+            &mut self.limiter
+        }
+    }
+
+    /// Runs a guest function that loops forever with the limits.
+    fn run_loop(limits: &RunLimits) -> Result<()> {
+        let mut config = Config::new();
+        config.epoch_interruption(limits.timeout.is_some());
+        config.consume_fuel(limits.fuel().is_some());
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, r#"(module (func (export "run") (loop (br 0))))"#)?;
+        let mut store = Store::new(&engine, Host { limiter: limits.limiter() });
+        limits.configure_store(&mut store)?;
+        let instance = Instance::new(&mut store, &module, &[])?;
+        let run = instance.get_typed_func::<(), ()>(&mut store, "run")?;
+        limits.run(&engine, &mut store, |store| run.call(store, ()))
+    }
+
+    #[test]
+    fn test_guest_limiter() {
+        const PAGE: usize = 1 << 16;
+        let limits = RunLimits {
+            max_memory: Some(4 * PAGE as u64),
+            max_table_elements: Some(10),
+            ..RunLimits::default()
+        };
+        let mut limiter = limits.limiter();
+
+        assert!(limiter.memory_growing(0, 2 * PAGE, None).unwrap());
+        // the failed growth does not count in the peak
+        assert!(limiter.memory_growing(2 * PAGE, 3 * PAGE, None).unwrap());
+        limiter.memory_grow_failed(anyhow!("Out of memory")).unwrap();
+        assert_eq!((limiter.memory, limiter.peak_memory), (2 * PAGE, 2 * PAGE));
+
+        // a growth to the limit is allowed
+        assert!(limiter.memory_growing(2 * PAGE, 4 * PAGE, None).unwrap());
+        assert_eq!((limiter.memory, limiter.peak_memory), (4 * PAGE, 4 * PAGE));
+        assert!(limiter.denied.is_none());
+
+        assert!(!limiter.memory_growing(4 * PAGE, 5 * PAGE, None).unwrap());
+        assert!(!limiter.table_growing(5, 11, None).unwrap());
+        assert!(limiter.table_growing(5, 10, None).unwrap());
+        // the first denial is kept
+        assert_eq!(
+            limiter.denied.as_deref(),
+            Some("growing a linear memory from 262144 to 327680 bytes over the limit of 262144 bytes")
+        );
+        assert_eq!(limiter.peak_memory, 4 * PAGE);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        // seconds without a suffix
+        assert_eq!(parse_duration("5").unwrap(), Duration::from_secs(5));
+
+        for invalid in ["0", "0ms", "", "ms", "-1s", "1h", "18446744073709551615m"] {
+            assert!(parse_duration(invalid).is_err(), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn test_fuel_per_inference() {
+        // 100 for loading the model and 30 for each of the 1 + 4 inferences
+        assert_eq!(fuel_per_inference(250, 130, 4), Some(30));
+        assert_eq!(fuel_per_inference(130, 130, 0), None);
+        assert_eq!(fuel_per_inference(100, 130, 4), None);
+    }
+
+    #[test]
+    fn test_limit_exit_codes() {
+        let limits = RunLimits { timeout: Some(Duration::from_millis(10)), ..RunLimits::default() };
+        let error = run_loop(&limits).unwrap_err();
+        assert_eq!(report_trap(&error, &GuestStderr::default()), Some(EXIT_TIMEOUT));
+
+        let limits = RunLimits { fuel_limit: Some(10_000), ..RunLimits::default() };
+        let error = run_loop(&limits).unwrap_err();
+        assert_eq!(report_trap(&error, &GuestStderr::default()), Some(EXIT_OUT_OF_FUEL));
+        // the fuel is reported also when the guest runs out of it
+        assert_eq!(limits.fuel_consumed.get(), Some(10_000));
+
+        // other errors are not traps
+        assert_eq!(report_trap(&anyhow!("Missing export"), &GuestStderr::default()), None);
+    }
+}
@@ -1,125 +1,373 @@
 extern crate wasmtime;
 extern crate wasmtime_wasi;
-extern crate wasi_common;
 extern crate anyhow;
-extern crate cap_std;
 extern crate local_names;
 extern crate wasmtime_wasi_nn;
+extern crate wasi_nn_onnx_backend;
+extern crate bytes;
 
-use anyhow::{Ok, Result};
-use local_names::{get_image_index, get_model_index};
-use std::{env, path::Path, time::Instant};
-use wasmtime::{Config, Engine, Module, Store};
-use wasi_common::{sync::Dir, sync::WasiCtxBuilder, WasiCtx};
-use wasmtime::component::__internal::wasmtime_environ::__core::result::Result::Ok as WasmtimeResultOk;
-use wasmtime_wasi_nn::{InMemoryRegistry, WasiNnCtx, backend::onnxruntime::OnnxBackend};
+mod cache;
+mod component;
+mod ctx;
+mod engine;
+mod invoke;
+mod limits;
+mod options;
+mod precompile;
+mod trap;
 
+use anyhow::{anyhow, Result};
+use local_names::{get_error_code, get_exit_code, ErrorType, InferenceOptions, ModelMetadata, RegistryBuilder, EVALUATE_OPTION, LISTING_HASH_ENV, REGISTRY_CONFIG_ENV};
+use std::{env, fs, path::Path, process, time::Instant};
+use wasmtime::{Engine, ExternType, Func, Instance, Module, Store};
+use cache::{CacheStatus, ModuleCache};
+use ctx::{Ctx, Preopen, WasiConfig};
+use engine::GuestEngine;
+use invoke::{Invocation, JsonFunctions};
+use options::{CompileOptions, Options};
+use trap::GuestStderr;
 
-/// The host state for running wasi-nn tests.
-struct Ctx {
-    wasi: WasiCtx,
-    wasi_nn: WasiNnCtx,
+/// The default function called in core Wasm modules.
+const FUNCTION_NAME: &str = "run_inference";
+/// The entry point of WASI command modules.
+const START_FUNCTION_NAME: &str = "_start";
+
+/// The exit code for invalid command line arguments.
+const EXIT_USAGE: i32 = 64;
+/// The exit code for errors in the host, for example a missing export. The traps in the guest have their own exit codes.
+const EXIT_HOST_ERROR: i32 = 70;
+
+
+fn main() {
+    let exit_code = match run() {
+        Ok(exit_code) => exit_code,
+        Err(error) => {
+            eprintln!("Error: {:#}", error);
+            EXIT_HOST_ERROR
+        }
+    };
+    process::exit(exit_code);
 }
-impl Ctx {
-    fn new(directories: &Vec<&str>) -> Result<Self> {
-        let preopen_dirs = directories
-            .iter()
-            .map(|dir| {
-                Dir::open_ambient_dir(Path::new(dir), cap_std::ambient_authority())
-            }.unwrap());
-
-        let mut binding = WasiCtxBuilder::new();
-        let builder = binding.inherit_stdio();
-        for (preopen_dir, path) in preopen_dirs.zip(directories) {
-            builder.preopened_dir(preopen_dir, path)?;
-        }
-
-        let wasi = builder.build();
-        let wasi_nn = WasiNnCtx::new(
-            [OnnxBackend::default().into()],
-            InMemoryRegistry::new().into()
-        );
-
-        Ok(Self { wasi, wasi_nn })
+
+fn run() -> Result<i32> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some(CompileOptions::COMMAND) {
+        return compile(&args);
+    }
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            println!("{}\n", error);
+            println!("{}", Options::usage(&args[0]));
+            return Ok(EXIT_USAGE);
+        }
+    };
+
+    if !verify_model(&options.model, options.print_hash) {
+        return Ok(get_exit_code(get_error_code(ErrorType::ModelLoad)));
+    }
+
+    // the configuration is printed first so that the timings can be grouped by the engine settings, creating the
+    // engine is timed as a part of creating the Wasm environment
+    println!("Engine configuration: {}", options.engine);
+    let start: Instant = Instant::now();
+    let engine = Engine::new(&options.engine.config()?)?;
+
+    let is_component = match options.precompiled {
+        true => precompile::is_component(&engine, &options.wasm_file)?,
+        false => component::is_component(&options.wasm_file)?,
+    };
+
+    let exit_code = run_guest(start, &options, &engine, is_component)?;
+    if exit_code == 0 && options.limits.fuel_per_inference {
+        print_fuel_per_inference(&options, &engine, is_component)?;
     }
+    Ok(exit_code)
 }
 
+/// Runs the guest with the options, timed from the start, and returns the process exit code.
+fn run_guest(start: Instant, options: &Options, engine: &Engine, is_component: bool) -> Result<i32> {
+    let wasm_module_filename: &str = &options.wasm_file;
+    let mut preopens = options.preopens.clone();
+    let mut envs = options.envs.clone();
+    let invocation = match (options.command, options.json) {
+        (true, _) => Invocation::Command,
+        (false, true) => Invocation::Json {
+            model: options.model.clone(),
+            image: options.image.clone(),
+            options: options.json_options.clone().unwrap_or_else(|| {
+                InferenceOptions { repeats: options.repeats }.to_json()
+            }),
+        },
+        (false, false) => {
+            let default_name = match is_component {
+                true => component::FUNCTION_NAME,
+                false => FUNCTION_NAME,
+            };
+            let name = options.invoke.clone().unwrap_or_else(|| default_name.to_string());
+            match options.invoke_args.is_empty() {
+                true => match default_function_args(options, &mut preopens, &mut envs) {
+                    Some(args) => Invocation::Function { name, args },
+                    None => return Ok(EXIT_USAGE),
+                },
+                false => Invocation::Function { name, args: options.invoke_args.clone() },
+            }
+        }
+    };
 
-fn main() -> wasmtime::Result<()> {
-    const MODEL_DIR: &str = "models";
-    const IMAGE_DIR: &str = "images";
-    let shared_dirs: Vec<&str> = vec![MODEL_DIR, IMAGE_DIR];
+    let guest_args = options.guest_args();
+    let guest_stderr = GuestStderr::default();
+    let wasi_config = WasiConfig {
+        preopens: &preopens,
+        args: &guest_args,
+        envs: &envs,
+        stderr: &guest_stderr,
+    };
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 5 {
-        println!("Usage: {} <wasm module> <model> <image> <number of repeats>", args[0]);
+    let cache = ModuleCache::new(&options.cache_dir);
+    if options.clear_cache {
+        let removed = cache.clear()?;
+        println!("Removed {} compiled modules from the cache {}", removed, options.cache_dir);
+    }
+    let cache = match (options.precompiled, options.no_cache) {
+        (true, _) => ModuleCache::precompiled(),
+        (false, true) => ModuleCache::disabled(),
+        (false, false) => cache,
+    };
+
+    let guest_engine = GuestEngine { engine, options: &options.engine, cache: &cache, limits: &options.limits };
+
+    let result = match is_component {
+        true => component::run(start, &guest_engine, wasm_module_filename, &wasi_config, &invocation),
+        false => run_module(start, &guest_engine, wasm_module_filename, &wasi_config, &invocation),
+    };
+    // the traps in the guest are reported with the backtrace and the panic message and have their own exit codes
+    result.or_else(|error| trap::report_trap(&error, &guest_stderr).ok_or(error))
+}
+
+/// Prints the fuel of a single inference for `--fuel-per-inference`. The fuel of the run also includes loading
+/// the model, so the guest is run again with 0 repeats and the difference of the fuel is divided by the repeats.
+fn print_fuel_per_inference(options: &Options, engine: &Engine, is_component: bool) -> Result<()> {
+    let evaluate = format!("--{}", EVALUATE_OPTION);
+    if !options.invoke_args.is_empty()
+        || options.json_options.is_some()
+        || options.guest_options.iter().any(|option| option.starts_with(&evaluate))
+    {
+        println!("Fuel per inference: not measured, the number of inferences is not given by the number of repeats");
         return Ok(());
     }
+    let fuel = match (options.repeats, options.limits.fuel_consumed.get()) {
+        (0, _) | (_, None) => {
+            println!("Fuel per inference: not measured with 0 repeats, the fuel above includes loading the model");
+            return Ok(());
+        }
+        (_, Some(fuel)) => fuel,
+    };
+
+    println!("\nRunning the guest again with 0 repeats for the fuel per inference\n");
+    let mut calibration = options.clone();
+    calibration.repeats = 0;
+    calibration.clear_cache = false;
+    let exit_code = run_guest(Instant::now(), &calibration, engine, is_component)?;
 
-    let wasm_module_filename: &str = &args[1];
-    let model_filename: &str = &args[2];
-    let image_name: &str = &args[3];
-    let model_index = match get_model_index(model_filename) {
+    match (exit_code, calibration.limits.fuel_consumed.get()) {
+        (0, Some(fuel_without_repeats)) => {
+            let inference = limits::fuel_per_inference(fuel, fuel_without_repeats, options.repeats).unwrap_or(0);
+            println!("Fuel per inference: {} (the average of {} repeats)", inference, options.repeats);
+            println!("Fuel for loading the model: {}", fuel_without_repeats.saturating_sub(inference));
+        }
+        _ => println!("Fuel per inference: not measured, the run with 0 repeats exited with {}", exit_code),
+    }
+    Ok(())
+}
+
+/// Runs the `compile` command, which precompiles a guest into a `.cwasm` file, and returns the process exit code.
+fn compile(args: &[String]) -> Result<i32> {
+    let options = match CompileOptions::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            println!("{}\n", error);
+            println!("{}", CompileOptions::usage(&args[0]));
+            return Ok(EXIT_USAGE);
+        }
+    };
+    precompile::compile(&options)?;
+    Ok(0)
+}
+
+/// Verifies the model file against the SHA-256 checksum in its metadata before the path is given to the guest,
+/// and prints the checksum if requested. The checksum is only computed when it is needed.
+fn verify_model(model_filename: &str, print_hash: bool) -> bool {
+    let metadata = match ModelMetadata::for_model(model_filename) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            println!("Error: {}", error);
+            return false;
+        }
+    };
+    if metadata.sha256.is_none() && !print_hash {
+        return true;
+    }
+    match metadata.verify_checksum(model_filename) {
+        Ok(checksum) => {
+            if print_hash {
+                println!("Model SHA-256: {}", checksum);
+            }
+            true
+        }
+        Err(error) => {
+            println!("Error: {}", error);
+            false
+        }
+    }
+}
+
+/// The arguments for the inference function when none are given: the model index, the image index and the number of repeats.
+/// The guest gets the registry configuration and the hash of the listing that the indexes refer to as environment variables
+/// and the folders of the registry are shared with the guest as read-only.
+fn default_function_args(
+    options: &Options,
+    preopens: &mut Vec<Preopen>,
+    envs: &mut Vec<(String, String)>,
+) -> Option<Vec<String>> {
+    let registry_builder = match &options.registry {
+        Some(filename) => {
+            let config = fs::read_to_string(filename)
+                .map_err(|error| format!("Could not read the registry configuration {}: {}", filename, error))
+                .and_then(|config| RegistryBuilder::from_json(&config));
+            match config {
+                Ok(builder) => builder,
+                Err(error) => {
+                    println!("{}", error);
+                    return None;
+                }
+            }
+        }
+        None => RegistryBuilder::new(),
+    };
+    let registry = match registry_builder.build() {
+        Ok(registry) => registry,
+        Err(error) => {
+            println!("{}", error);
+            return None;
+        }
+    };
+
+    let model_filename: &str = &options.model;
+    let image_name: &str = &options.image;
+    let model_index = match registry.model_index(model_filename) {
         Some(index) => index,
         None => {
             println!("Model not found: {}", model_filename);
-            return Ok(());
+            return None;
         }
     };
-    let image_index = match get_image_index(image_name) {
+    let image_index = match registry.image_index(image_name) {
         Some(index) => index,
         None => {
             println!("Image not found: {}", image_name);
-            return Ok(());
+            return None;
         }
     };
-    let repeats: u32 = args[4].parse().unwrap();
 
-    let start: Instant = Instant::now();
+    let manifest_folder = registry_builder
+        .existing_manifest()
+        .map(|manifest| Path::new(manifest).parent().and_then(|folder| folder.to_str()).unwrap_or(""));
+    let shared_folders = registry_builder.folders().iter().cloned().chain(manifest_folder).collect::<Vec<&str>>();
+    for folder in shared_folders {
+        let folder = match folder.trim_end_matches('/') {
+            "" => ".",
+            folder => folder,
+        };
+        if !preopens.iter().any(|preopen| preopen.guest_path == folder) {
+            preopens.push(Preopen::read_only(folder, folder));
+        }
+    }
+    envs.push((REGISTRY_CONFIG_ENV.to_string(), registry_builder.to_json()));
+    envs.push((LISTING_HASH_ENV.to_string(), registry.hash().to_string()));
 
-    let config = Config::default();
-    let engine = Engine::new(&config)?;
-    let mut linker = wasmtime::Linker::new(&engine);
+    Some(vec![model_index.to_string(), image_index.to_string(), options.repeats.to_string()])
+}
+
+/// Runs a core Wasm module and returns the process exit code. The creation of the Wasm environment is timed from `start`.
+/// The compiled module is loaded from the cache or compiled and written to it. The called function is run with the limits.
+fn run_module(
+    start: Instant,
+    guest_engine: &GuestEngine,
+    wasm_module_filename: &str,
+    wasi_config: &WasiConfig,
+    invocation: &Invocation,
+) -> Result<i32> {
+    let GuestEngine { engine, options: engine_options, cache, limits } = *guest_engine;
+    let mut linker = wasmtime::Linker::new(engine);
 
-    wasi_common::sync::add_to_linker(&mut linker, |host: &mut Ctx| &mut host.wasi)?;
+    wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |host: &mut Ctx| &mut host.wasi)?;
     wasmtime_wasi_nn::witx::add_to_linker(&mut linker, |host| &mut host.wasi_nn)?;
 
     let mut store = Store::new(
-        &engine,
-        Ctx::new(&shared_dirs)?
+        engine,
+        Ctx::new(wasi_config, limits.limiter())?
     );
+    limits.configure_store(&mut store)?;
     let environment_set_time = start.elapsed();
 
-    let wasm_module_serialized_name = wasm_module_filename.to_string() + ".SERIALIZED";
-    let wasm_module =
-        match unsafe { Module::deserialize_file(&engine, wasm_module_serialized_name.clone()) } {
-            WasmtimeResultOk(serialized_module) => serialized_module,
-            Err(_) => {
-                let loaded_module = Module::from_file(&engine, wasm_module_filename)?;
-                let byte_module = loaded_module.serialize()?;
-                std::fs::write(wasm_module_serialized_name, byte_module).unwrap();
-
-                loaded_module
-            }
-        };
+    let (wasm_module, cache_status): (Module, CacheStatus) = cache.load(engine, engine_options, wasm_module_filename)?;
 
-    // add the module to the linker
-    const MODULE_NAME: &str = "test";
-    const FUNCTION_NAME: &str = "run_inference";
-    linker.module(&mut store, MODULE_NAME, &wasm_module)?;
+    let instance = linker.instantiate(&mut store, &wasm_module)?;
     let module_load_time = start.elapsed() - environment_set_time;
 
-    let inference_function = linker
-        .get(&mut store, MODULE_NAME, FUNCTION_NAME).unwrap()
-        .into_func().unwrap()
-        .typed::<(i32, i32, u32), (i32,)>(&mut store).unwrap();
-    let function_load_time = start.elapsed() - environment_set_time - module_load_time;
+    let print_load_times = |function_load_time| {
+        println!("Creating the Wasm environment took: {:?}", environment_set_time);
+        println!("Loading the Wasm module took: {:?} ({})", module_load_time, cache_status);
+        println!("Loading the Wasm function took: {:?}\n", function_load_time);
+    };
 
-    println!("Creating the Wasm environment took: {:?}", environment_set_time);
-    println!("Loading the Wasm module took: {:?}", module_load_time);
-    println!("Loading the Wasm function took: {:?}\n", function_load_time);
+    match invocation {
+        Invocation::Function { name, args } => {
+            let inference_function = get_function(&mut store, &instance, &wasm_module, wasm_module_filename, name)?;
+            print_load_times(start.elapsed() - environment_set_time - module_load_time);
 
-    let _result = inference_function.call(&mut store, (model_index, image_index, repeats));
+            let result = limits.run(engine, &mut store, |store| {
+                invoke::call_core_function(store, &inference_function, name, args)
+            })?;
+            Ok(invoke::function_exit_code(name, result))
+        }
+        Invocation::Command => {
+            let start_function = get_function(&mut store, &instance, &wasm_module, wasm_module_filename, START_FUNCTION_NAME)?
+                .typed::<(), ()>(&store)?;
+            print_load_times(start.elapsed() - environment_set_time - module_load_time);
 
-    Ok(())
+            invoke::command_exit_code(limits.run(engine, &mut store, |store| start_function.call(store, ())))
+        }
+        Invocation::Json { model, image, options } => {
+            let json_functions = JsonFunctions::new(&mut store, &instance)?;
+            print_load_times(start.elapsed() - environment_set_time - module_load_time);
+
+            let result_json = limits.run(engine, &mut store, |store| json_functions.call(store, model, image, options))?;
+            invoke::json_exit_code(&result_json)
+        }
+    }
+}
+
+/// Gets an exported function from the module instance, the error lists the exported functions.
+fn get_function(
+    store: &mut Store<Ctx>,
+    instance: &Instance,
+    wasm_module: &Module,
+    wasm_module_filename: &str,
+    function_name: &str,
+) -> Result<Func> {
+    instance
+        .get_func(store, function_name)
+        .ok_or_else(|| {
+            let functions: Vec<&str> = wasm_module
+                .exports()
+                .filter(|export| matches!(export.ty(), ExternType::Func(_)))
+                .map(|export| export.name())
+                .collect();
+            anyhow!(
+                "The Wasm module {} does not export a function named {} (exported functions: {})",
+                wasm_module_filename, function_name, functions.join(", ")
+            )
+        })
 }
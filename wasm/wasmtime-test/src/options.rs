@@ -0,0 +1,369 @@
+// Command line options for the wasmtime test program.
+
+use anyhow::{anyhow, Result};
+use std::{env, path::Path, str::FromStr};
+use cache::DEFAULT_CACHE_DIR;
+use ctx::Preopen;
+use engine::{self, Compiler, EngineOptions};
+use limits::{self, RunLimits};
+
+/// The usage of the engine options, shared by running and compiling the guests.
+const ENGINE_USAGE: &str = "  \
+    --compiler <cranelift|winch>    the compiler for the Wasm code (default: cranelift)\n  \
+    --opt-level <level>             the Cranelift optimization level: none, speed or speed_and_size (default: speed)\n  \
+    --no-simd                       disable the SIMD and relaxed SIMD proposals\n  \
+    --no-relaxed-simd               disable the relaxed SIMD proposal\n  \
+    --memory-reservation <size>     the maximum size of a statically reserved linear memory, 0 for explicit bounds checks\n  \
+    --memory-guard-size <size>      the size of the guard region after the linear memories\n  \
+    --no-parallel-compilation       compile the functions on a single thread\n  \
+    --nan-canonicalization          canonicalize the NaN values in Cranelift\n  \
+    --epoch-interruption            compile the epoch checks for --timeout (implied by --timeout)\n  \
+    --consume-fuel                  compile the fuel counting for --fuel (implied by --fuel and --fuel-limit)\n  \
+    --backtrace-details             show the source files and lines in the trap backtraces (needs debug info in the guest)\n  \
+    --target <triple>               the target triple to compile for (default: the host)\n  \
+    --cpu-features <flags>          comma-separated Cranelift CPU feature flags, for example has_avx2 or has_lse\n\
+    \n\
+    The sizes are in bytes with an optional K, M or G suffix. Without --target and --cpu-features the features of\n\
+    the host CPU are used.";
+
+/// The directories that are always shared with the guest as read-only.
+const MODEL_DIR: &str = "models";
+const IMAGE_DIR: &str = "images";
+
+
+#[derive(Clone)]
+pub struct Options {
+    pub wasm_file: String,
+    pub model: String,
+    pub image: String,
+    pub repeats: u32,
+    pub preopens: Vec<Preopen>,
+    pub envs: Vec<(String, String)>,
+    /// The exported function to call, the default inference function when not given.
+    pub invoke: Option<String>,
+    /// The arguments for the called function, the model index, image index and repeats when not given.
+    pub invoke_args: Vec<String>,
+    /// Whether to run the guest as a WASI command instead of calling an exported function.
+    pub command: bool,
+    /// The options given to the guest after the positional arguments (the arguments after `--`).
+    pub guest_options: Vec<String>,
+    /// Whether to pass the paths and options to the guest as strings and read back a JSON result.
+    pub json: bool,
+    /// The JSON options for the inference with `--json`, by default only the number of repeats.
+    pub json_options: Option<String>,
+    /// The JSON file with the local-names registry configuration used for the model and image indexes.
+    pub registry: Option<String>,
+    /// Whether to print the SHA-256 checksum of the model file before running the guest.
+    pub print_hash: bool,
+    /// The settings of the Wasmtime engine.
+    pub engine: EngineOptions,
+    /// The directory of the compiled module cache.
+    pub cache_dir: String,
+    /// Whether to compile the guest without reading or writing the cache.
+    pub no_cache: bool,
+    /// Whether to remove the compiled code from the cache directory before running the guest.
+    pub clear_cache: bool,
+    /// Whether the guest is a `.cwasm` file precompiled with `compile`, which is loaded without compiling anything.
+    pub precompiled: bool,
+    /// The timeout and fuel of the called guest function.
+    pub limits: RunLimits,
+}
+
+impl Options {
+    /// Parses the options from the command line arguments (including the program name).
+    pub fn parse(args: &[String]) -> Result<Options> {
+        let mut positional: Vec<&str> = Vec::new();
+        let mut preopens: Vec<Preopen> = vec![
+            Preopen::read_only(MODEL_DIR, MODEL_DIR),
+            Preopen::read_only(IMAGE_DIR, IMAGE_DIR),
+        ];
+        let mut envs: Vec<(String, String)> = Vec::new();
+        let mut invoke: Option<String> = None;
+        let mut invoke_args: Vec<String> = Vec::new();
+        let mut command = false;
+        let mut guest_options: Vec<String> = Vec::new();
+        let mut json = false;
+        let mut json_options: Option<String> = None;
+        let mut registry: Option<String> = None;
+        let mut print_hash = false;
+        let mut engine = EngineOptions::default();
+        let mut cache_dir = DEFAULT_CACHE_DIR.to_string();
+        let mut no_cache = false;
+        let mut clear_cache = false;
+        let mut precompiled = false;
+        let mut limits = RunLimits::default();
+
+        let mut arg_iter = args.iter().skip(1);
+        while let Some(arg) = arg_iter.next() {
+            let mut value = |name: &str| {
+                arg_iter.next().ok_or_else(|| anyhow!("Missing value for option {}", name))
+            };
+            match arg.as_str() {
+                "--dir" => preopens.push(parse_dir(value("--dir")?)),
+                "--env" => envs.push(parse_env(value("--env")?)?),
+                "--invoke" => invoke = Some(value("--invoke")?.to_string()),
+                "--arg" => invoke_args.push(value("--arg")?.to_string()),
+                "--command" => command = true,
+                "--json" => json = true,
+                "--options" => json_options = Some(value("--options")?.to_string()),
+                "--registry" => registry = Some(value("--registry")?.to_string()),
+                "--print-hash" => print_hash = true,
+                "--cache-dir" => cache_dir = value("--cache-dir")?.to_string(),
+                "--no-cache" => no_cache = true,
+                "--clear-cache" => clear_cache = true,
+                "--precompiled" => precompiled = true,
+                "--timeout" => limits.timeout = Some(limits::parse_duration(value("--timeout")?)?),
+                "--fuel" => limits.measure_fuel = true,
+                "--fuel-per-inference" => {
+                    limits.measure_fuel = true;
+                    limits.fuel_per_inference = true;
+                }
+                "--max-memory" => limits.max_memory = Some(engine::parse_size(value("--max-memory")?)?),
+                "--max-table-elements" => limits.max_table_elements = Some(parse_count(value("--max-table-elements")?)?),
+                "--max-instances" => limits.max_instances = Some(parse_count(value("--max-instances")?)?),
+                "--max-tables" => limits.max_tables = Some(parse_count(value("--max-tables")?)?),
+                "--fuel-limit" => {
+                    let limit = value("--fuel-limit")?;
+                    limits.fuel_limit = Some(limit.parse().map_err(|_| anyhow!("Invalid fuel limit: {}", limit))?);
+                }
+                "--" => {
+                    guest_options.extend(arg_iter.by_ref().cloned());
+                    break;
+                }
+                option if option.starts_with("--") => {
+                    if !parse_engine_option(&mut engine, option, &mut arg_iter)? {
+                        return Err(anyhow!("Unknown option: {}", option));
+                    }
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        if positional.len() != 4 {
+            return Err(anyhow!("Expected 4 positional arguments, got {}", positional.len()));
+        }
+        if command && (invoke.is_some() || !invoke_args.is_empty()) {
+            return Err(anyhow!("The option --command cannot be used together with --invoke or --arg"));
+        }
+        if json && (command || invoke.is_some() || !invoke_args.is_empty()) {
+            return Err(anyhow!("The option --json cannot be used together with --command, --invoke or --arg"));
+        }
+        if json_options.is_some() && !json {
+            return Err(anyhow!("The option --options can only be used with --json"));
+        }
+        // the code checking the epoch and counting the fuel is only compiled in when it is needed
+        engine.epoch_interruption |= limits.timeout.is_some();
+        engine.consume_fuel |= limits.fuel().is_some();
+        let repeats: u32 = positional[3]
+            .parse()
+            .map_err(|_| anyhow!("Invalid number of repeats: {}", positional[3]))?;
+
+        Ok(Options {
+            wasm_file: positional[0].to_string(),
+            model: positional[1].to_string(),
+            image: positional[2].to_string(),
+            repeats,
+            preopens,
+            envs,
+            invoke,
+            invoke_args,
+            command,
+            guest_options,
+            json,
+            json_options,
+            registry,
+            print_hash,
+            engine,
+            cache_dir,
+            no_cache,
+            clear_cache,
+            precompiled,
+            limits,
+        })
+    }
+
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {} [options] <wasm module or component> <model> <image> <number of repeats> [-- <guest options>]\n\
+            \n\
+            Options:\n  \
+              --dir <host dir>[::<guest dir>]  share an additional directory with the guest (read-write)\n  \
+              --env <name>[=<value>]          set an environment variable for the guest, without a value the host value is used\n  \
+              --invoke <function>             the exported function to call (default: run_inference, run-inference for components)\n  \
+              --arg <value>                   an argument for the called function, repeated for each parameter\n  \
+              --command                       run the guest as a WASI command with the positional arguments and guest options as its arguments\n  \
+              --json                          pass the model and image paths and JSON options to the guest as strings and print the JSON result\n  \
+              --options <json>                the JSON options for --json (default: {{\"repeats\": <number of repeats>}})\n  \
+              --registry <json file>          the folders, patterns and extensions for finding the model and image indexes\n  \
+              --print-hash                    print the SHA-256 checksum of the model file\n  \
+              --cache-dir <dir>               the directory of the compiled module cache (default: {})\n  \
+              --no-cache                      compile the guest without reading or writing the cache\n  \
+              --clear-cache                   remove the compiled modules from the cache directory before running\n  \
+              --precompiled                   the guest is a .cwasm file from the compile command, nothing is compiled\n  \
+              --timeout <duration>            interrupt the called guest function after the duration, for example 30s or 500ms\n  \
+              --fuel                          measure the fuel consumed by the called guest function\n  \
+              --fuel-per-inference            also measure the fuel of a single inference by running the guest again with 0 repeats\n  \
+              --fuel-limit <amount>           measure the fuel and stop the guest when the amount is consumed\n  \
+              --max-memory <size>             the maximum size of each linear memory of the guest\n  \
+              --max-table-elements <count>    the maximum number of elements in each table of the guest\n  \
+              --max-instances <count>         the maximum number of instances (default: 10000)\n  \
+              --max-tables <count>            the maximum number of tables (default: 10000)\n\
+            \n\
+            Engine options:\n\
+            {}\n\
+            \n\
+            The engine configuration is printed with the timings.\n\
+            \n\
+            Without --arg the called function gets the model index, the image index and the number of repeats.\n\
+            The model file is verified against the SHA-256 checksum in its metadata file before running the guest.\n\
+            \n\
+            The folders '{}' and '{}' are always shared with the guest as read-only.\n\
+            \n\
+            Precompile a guest for --precompiled with: {} {} [options] <wasm module or component>",
+            program, DEFAULT_CACHE_DIR, ENGINE_USAGE, MODEL_DIR, IMAGE_DIR, program, CompileOptions::COMMAND
+        )
+    }
+
+    /// The command line arguments given to the guest.
+    pub fn guest_args(&self) -> Vec<String> {
+        let mut guest_args = vec![self.wasm_file.clone(), self.model.clone(), self.image.clone(), self.repeats.to_string()];
+        guest_args.extend_from_slice(&self.guest_options);
+        guest_args
+    }
+}
+
+
+/// The options of the `compile` command, which precompiles a guest into a `.cwasm` file.
+pub struct CompileOptions {
+    pub wasm_file: String,
+    /// The precompiled file, by default the Wasm file with the `.cwasm` extension.
+    pub output: String,
+    pub engine: EngineOptions,
+}
+
+impl CompileOptions {
+    /// The name of the command given as the first argument.
+    pub const COMMAND: &'static str = "compile";
+
+    /// Parses the options from the command line arguments (including the program name and the command).
+    pub fn parse(args: &[String]) -> Result<CompileOptions> {
+        let mut positional: Vec<&str> = Vec::new();
+        let mut output: Option<String> = None;
+        let mut engine = EngineOptions::default();
+
+        let mut arg_iter = args.iter().skip(2);
+        while let Some(arg) = arg_iter.next() {
+            match arg.as_str() {
+                "--output" => {
+                    output = Some(arg_iter.next().ok_or_else(|| anyhow!("Missing value for option --output"))?.to_string())
+                }
+                option if option.starts_with("--") => {
+                    if !parse_engine_option(&mut engine, option, &mut arg_iter)? {
+                        return Err(anyhow!("Unknown option: {}", option));
+                    }
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        if positional.len() != 1 {
+            return Err(anyhow!("Expected the Wasm file as the only positional argument, got {}", positional.len()));
+        }
+        let wasm_file = positional[0].to_string();
+        let output = output.unwrap_or_else(|| {
+            Path::new(&wasm_file).with_extension("cwasm").to_string_lossy().to_string()
+        });
+
+        Ok(CompileOptions { wasm_file, output, engine })
+    }
+
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {} {} [options] <wasm module or component>\n\
+            \n\
+            Precompiles the guest for the target into a .cwasm file that is run with --precompiled.\n\
+            \n\
+            Options:\n  \
+              --output <file>                 the precompiled file (default: the Wasm file with the .cwasm extension)\n\
+            \n\
+            Engine options:\n\
+            {}\n\
+            \n\
+            The precompiled file is run with the same engine options without --target and --cpu-features.",
+            program, CompileOptions::COMMAND, ENGINE_USAGE
+        )
+    }
+}
+
+
+/// Parses an engine option and its value from the arguments. Returns false if the option is not an engine option.
+fn parse_engine_option<'a, I>(engine: &mut EngineOptions, option: &str, arg_iter: &mut I) -> Result<bool>
+where I: Iterator<Item = &'a String> {
+    let mut value = |name: &str| arg_iter.next().ok_or_else(|| anyhow!("Missing value for option {}", name));
+    match option {
+        "--compiler" => engine.compiler = Compiler::from_name(value(option)?)?,
+        "--opt-level" => engine.opt_level = engine::parse_opt_level(value(option)?)?,
+        "--no-simd" => engine.simd = false,
+        "--no-relaxed-simd" => engine.relaxed_simd = false,
+        "--memory-reservation" => engine.memory_reservation = Some(engine::parse_size(value(option)?)?),
+        "--memory-guard-size" => engine.memory_guard_size = Some(engine::parse_size(value(option)?)?),
+        "--no-parallel-compilation" => engine.parallel_compilation = false,
+        "--nan-canonicalization" => engine.nan_canonicalization = true,
+        "--epoch-interruption" => engine.epoch_interruption = true,
+        "--consume-fuel" => engine.consume_fuel = true,
+        "--backtrace-details" => engine.backtrace_details = true,
+        "--target" => engine.target = Some(value(option)?.to_string()),
+        "--cpu-features" => engine.cpu_features.extend(
+            value(option)?.split(',').map(|feature| feature.trim().to_string()).filter(|feature| !feature.is_empty()),
+        ),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_count<T: FromStr>(value: &str) -> Result<T> {
+    value.parse().map_err(|_| anyhow!("Invalid count: {}", value))
+}
+
+fn parse_dir(value: &str) -> Preopen {
+    match value.split_once("::") {
+        Some((host_path, guest_path)) => Preopen::read_write(host_path, guest_path),
+        None => Preopen::read_write(value, value),
+    }
+}
+
+fn parse_env(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((name, env_value)) => Ok((name.to_string(), env_value.to_string())),
+        None => env::var(value)
+            .map(|env_value| (value.to_string(), env_value))
+            .map_err(|_| anyhow!("Environment variable {} is not set on the host", value)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_args(args: &[&str]) -> Vec<String> {
+        ["wasmtime-test", CompileOptions::COMMAND].iter().chain(args).map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_compile_options() {
+        let options = CompileOptions::parse(&compile_args(&["bin/simple-onnx.wasm"])).unwrap();
+        assert_eq!(options.wasm_file, "bin/simple-onnx.wasm");
+        assert_eq!(options.output, "bin/simple-onnx.cwasm");
+
+        let options = CompileOptions::parse(&compile_args(&["--output", "guest-aarch64.cwasm", "guest.wasm"])).unwrap();
+        assert_eq!(options.output, "guest-aarch64.cwasm");
+
+        // the Wasm file is missing
+        assert!(CompileOptions::parse(&compile_args(&[])).is_err());
+        assert!(CompileOptions::parse(&compile_args(&["--output", "guest.cwasm"])).is_err());
+        assert!(CompileOptions::parse(&compile_args(&["guest.wasm", "--output"])).is_err());
+        assert!(CompileOptions::parse(&compile_args(&["guest.wasm", "other.wasm"])).is_err());
+        assert!(CompileOptions::parse(&compile_args(&["guest.wasm", "--unknown"])).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// Ahead-of-time compilation of the guests into `.cwasm` files, possibly for another target and CPU features,
+// so that the devices running the guests with `--precompiled` only load the compiled code and never compile.
+
+use anyhow::{anyhow, Result};
+use std::{fs, time::Instant};
+use wasmtime::{Engine, Precompiled};
+use component;
+use options::CompileOptions;
+
+
+/// Precompiles the Wasm module or component into the output file.
+pub fn compile(options: &CompileOptions) -> Result<()> {
+    println!("Engine configuration: {}", options.engine);
+    let engine = Engine::new(&options.engine.config()?)?;
+    let wasm_bytes = fs::read(&options.wasm_file)
+        .map_err(|error| anyhow!("Could not read the Wasm file {}: {}", options.wasm_file, error))?;
+
+    let start = Instant::now();
+    let compiled = match component::is_component(&options.wasm_file)? {
+        true => engine.precompile_component(&wasm_bytes)?,
+        false => engine.precompile_module(&wasm_bytes)?,
+    };
+    println!("Compiling {} took: {:?}", options.wasm_file, start.elapsed());
+
+    fs::write(&options.output, &compiled)
+        .map_err(|error| anyhow!("Could not write the precompiled file {}: {}", options.output, error))?;
+    println!("Wrote {} ({} bytes)", options.output, compiled.len());
+    Ok(())
+}
+
+/// Checks whether the precompiled file is a component instead of a core module.
+pub fn is_component(engine: &Engine, filename: &str) -> Result<bool> {
+    let not_precompiled = |reason: String| {
+        anyhow!(
+            "Not a precompiled Wasm file: {} ({}), precompile the guest with the {} command",
+            filename, reason, CompileOptions::COMMAND
+        )
+    };
+    match engine.detect_precompiled_file(filename) {
+        Ok(Some(Precompiled::Module)) => Ok(false),
+        Ok(Some(Precompiled::Component)) => Ok(true),
+        Ok(None) => Err(not_precompiled("no Wasmtime code in the file".to_string())),
+        Err(error) => Err(not_precompiled(format!("{:#}", error))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf, process};
+    use wasmtime::{component::Component, Module};
+
+    fn write_temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("wasmtime-test-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_is_component() {
+        let engine = Engine::default();
+        let module = Module::new(&engine, "(module)").unwrap().serialize().unwrap();
+        let component = Component::new(&engine, "(component)").unwrap().serialize().unwrap();
+        let module_file = write_temp_file("module.cwasm", &module);
+        let component_file = write_temp_file("component.cwasm", &component);
+        let wasm_file = write_temp_file("module.wasm", b"\0asm\x01\0\0\0");
+
+        assert!(!is_component(&engine, module_file.to_str().unwrap()).unwrap());
+        assert!(is_component(&engine, component_file.to_str().unwrap()).unwrap());
+
+        // a Wasm file that is not precompiled and a missing file
+        for file in [wasm_file.to_str().unwrap(), "missing.cwasm"] {
+            let error = is_component(&engine, file).unwrap_err().to_string();
+            assert!(error.starts_with(&format!("Not a precompiled Wasm file: {} (", file)), "{}", error);
+            assert!(error.ends_with("precompile the guest with the compile command"), "{}", error);
+        }
+
+        for path in [module_file, component_file, wasm_file] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// The reporting of the traps in the guest: the trap with its Wasm backtrace, symbolicated from the DWARF debug
+// information of the guest with `--backtrace-details`, and the panic message of a Rust guest, which is printed to
+// the guest stderr before the panic aborts the guest with a trap. The guest stderr is passed through to the host
+// stderr and its end is kept for finding the panic message.
+
+use anyhow::Error;
+use bytes::Bytes;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use wasmtime::{Trap, WasmBacktrace};
+use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamResult, Subscribe};
+
+/// The exit code when the guest is interrupted by the timeout, like with the `timeout` command.
+pub const EXIT_TIMEOUT: i32 = 124;
+/// The exit code when the guest runs out of fuel.
+pub const EXIT_OUT_OF_FUEL: i32 = 123;
+/// The exit code for other traps in the guest, for example a panic in a Rust guest.
+pub const EXIT_TRAP: i32 = 125;
+
+/// The number of bytes kept from the end of the guest stderr.
+const STDERR_TAIL_LENGTH: usize = 4096;
+/// The start of the panic message of a Rust guest, for example `thread 'main' panicked at src/main.rs:10:5:`.
+const PANIC_MARKER: &str = "panicked at ";
+/// The start of the line that the Rust panic handler prints after the panic message.
+const PANIC_NOTE: &str = "note: ";
+
+
+/// The stderr of the guest, which writes to the host stderr and keeps the end of the output.
+#[derive(Clone, Default)]
+pub struct GuestStderr {
+    tail: Arc<Mutex<Vec<u8>>>,
+}
+
+impl GuestStderr {
+    /// The panic message of a Rust guest from the end of its stderr, for example
+    /// `panicked at src/main.rs:10:5: index out of bounds`.
+    pub fn panic_message(&self) -> Option<String> {
+        let tail = self.tail.lock().map(|tail| String::from_utf8_lossy(&tail).to_string()).ok()?;
+        let start = tail.rfind(PANIC_MARKER)?;
+        let message: Vec<&str> = tail[start..]
+            .lines()
+            .take_while(|line| !line.starts_with(PANIC_NOTE))
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        Some(message.join(" "))
+    }
+}
+
+impl StdoutStream for GuestStderr {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(TeeStream { inner: wasmtime_wasi::stderr().stream(), tail: self.tail.clone() })
+    }
+
+    fn isatty(&self) -> bool {
+        wasmtime_wasi::stderr().isatty()
+    }
+}
+
+/// A stream that writes to the host stderr and keeps the end of the written bytes.
+struct TeeStream {
+    inner: Box<dyn HostOutputStream>,
+    tail: Arc<Mutex<Vec<u8>>>,
+}
+
+impl HostOutputStream for TeeStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        if let Ok(mut tail) = self.tail.lock() {
+            tail.extend_from_slice(&bytes);
+            let excess = tail.len().saturating_sub(STDERR_TAIL_LENGTH);
+            tail.drain(..excess);
+        }
+        self.inner.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+}
+
+// the asynchronous trait method written out, since `async fn` is not available in the 2015 edition
+impl Subscribe for TeeStream {
+    fn ready<'a, 'b>(&'a mut self) -> Pin<Box<dyn Future<Output = ()> + Send + 'b>>
+    where 'a: 'b, Self: 'b {
+        self.inner.ready()
+    }
+}
+
+
+/// Prints the report of a trap in the guest and returns the exit code for it, or `None` if the error is not a trap.
+pub fn report_trap(error: &Error, stderr: &GuestStderr) -> Option<i32> {
+    let trap = error.downcast_ref::<Trap>()?;
+    let (exit_code, description) = match trap {
+        Trap::Interrupt => (EXIT_TIMEOUT, "The guest was interrupted by the timeout"),
+        Trap::OutOfFuel => (EXIT_OUT_OF_FUEL, "The guest ran out of fuel"),
+        _ => (EXIT_TRAP, "The guest trapped"),
+    };
+
+    eprintln!("Error: {}: {}", description, trap);
+    if let Some(message) = stderr.panic_message() {
+        eprintln!("The guest {}", message);
+    }
+    match error.downcast_ref::<WasmBacktrace>() {
+        Some(backtrace) => eprintln!("{}", backtrace),
+        None => eprintln!("{:#}", error),
+    }
+    Some(exit_code)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guest_stderr(output: &str) -> GuestStderr {
+        GuestStderr { tail: Arc::new(Mutex::new(output.as_bytes().to_vec())) }
+    }
+
+    #[test]
+    fn test_panic_message() {
+        // the format of Rust 1.73 and later, with the location and the message on separate lines
+        let stderr = guest_stderr(
+            "Loading the model took 13ms\n\
+             thread 'main' panicked at src/main.rs:2:5:\n\
+             index out of bounds: the len is 3 but the index is 9\n",
+        );
+        assert_eq!(
+            stderr.panic_message().unwrap(),
+            "panicked at src/main.rs:2:5: index out of bounds: the len is 3 but the index is 9"
+        );
+
+        // the note of the panic handler is not a part of the message
+        let stderr = guest_stderr(
+            "thread 'main' panicked at src/lib.rs:40:9:\n\
+             called `Option::unwrap()` on a `None` value\n\
+             note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace\n",
+        );
+        assert_eq!(
+            stderr.panic_message().unwrap(),
+            "panicked at src/lib.rs:40:9: called `Option::unwrap()` on a `None` value"
+        );
+
+        assert_eq!(guest_stderr("Error: ModelLoad (-4): could not load the model\n").panic_message(), None);
+        assert_eq!(guest_stderr("").panic_message(), None);
+    }
+}
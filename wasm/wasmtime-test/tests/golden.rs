@@ -0,0 +1,52 @@
+// Golden-output regression tests: runs the Wasm guests built to the bin folder with wasmtime-test using the models
+// in the bin folder and the bundled images, with the checks of `test_models::golden`. Missing guests are skipped.
+
+extern crate test_models;
+
+use std::{path::Path, process::Command};
+use test_models::golden::{check_golden_outputs, REPEATS};
+
+const BIN_FOLDER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../bin");
+
+
+/// Runs the guest with every model and golden image that have expected values and checks the results.
+/// The engine options are given to wasmtime-test before the guest.
+fn check_guest_golden_outputs(guest: &str, engine_options: &[&str]) {
+    if !Path::new(BIN_FOLDER).join(guest).is_file() {
+        eprintln!("Skipping the golden-output tests for {}: the guest has not been built to {}", guest, BIN_FOLDER);
+        return;
+    }
+
+    check_golden_outputs(BIN_FOLDER, guest, |model, image| {
+        Command::new(env!("CARGO_BIN_EXE_wasmtime-test"))
+            .args(engine_options)
+            .args([guest, model, image, REPEATS])
+            .current_dir(BIN_FOLDER)
+            .output()
+            .expect("could not run wasmtime-test")
+    });
+}
+
+#[test]
+fn wasi_nn_module_golden_outputs() {
+    check_guest_golden_outputs("wasi-nn-onnx-test.wasm", &[]);
+}
+
+#[test]
+fn wasi_nn_component_golden_outputs() {
+    check_guest_golden_outputs("wasi-nn-onnx-test.component.wasm", &[]);
+}
+
+#[test]
+fn simple_onnx_golden_outputs() {
+    check_guest_golden_outputs("simple-onnx.wasm", &[]);
+}
+
+/// The engine settings change the speed of the guest but not its results.
+#[test]
+fn simple_onnx_golden_outputs_with_engine_options() {
+    check_guest_golden_outputs(
+        "simple-onnx.wasm",
+        &["--opt-level", "none", "--no-simd", "--memory-reservation", "0", "--nan-canonicalization"],
+    );
+}